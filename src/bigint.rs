@@ -0,0 +1,450 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// Arbitrary-precision integer in sign-magnitude form.
+// `digits` holds base 2^32 limbs, least significant first, with no
+// trailing zero limbs; zero is the empty vector and is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>
+}
+
+type Digits = Vec<u32>;
+
+const BASE: u64 = 1 << 32;
+
+fn trim(digits: &mut Digits) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn mag_cmp(x: &[u32], y: &[u32]) -> Ordering {
+    x.len().cmp(&y.len()).then_with(|| x.iter().rev().cmp(y.iter().rev()))
+}
+
+fn mag_add(x: &[u32], y: &[u32]) -> Digits {
+    let (x, y) = if x.len() >= y.len() { (x, y) } else { (y, x) };
+    let mut ret = Vec::with_capacity(x.len() + 1);
+    let mut carry = 0;
+    for (i, &d) in x.iter().enumerate() {
+        let s = d as u64 + *y.get(i).unwrap_or(&0) as u64 + carry;
+        ret.push(s as u32);
+        carry = s >> 32;
+    }
+    if carry > 0 {
+        ret.push(carry as u32);
+    }
+    ret
+}
+
+// Requires |x| >= |y|.
+fn mag_sub(x: &[u32], y: &[u32]) -> Digits {
+    let mut ret = Vec::with_capacity(x.len());
+    let mut borrow = 0;
+    for (i, &d) in x.iter().enumerate() {
+        let s = d as i64 - *y.get(i).unwrap_or(&0) as i64 - borrow;
+        if s < 0 {
+            ret.push((s + BASE as i64) as u32);
+            borrow = 1;
+        } else {
+            ret.push(s as u32);
+            borrow = 0;
+        }
+    }
+    trim(&mut ret);
+    ret
+}
+
+fn mag_mul(x: &[u32], y: &[u32]) -> Digits {
+    if x.is_empty() || y.is_empty() {
+        return vec![];
+    }
+    let mut ret = vec![0u32; x.len() + y.len()];
+    for (i, &a) in x.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &b) in y.iter().enumerate() {
+            let t = a as u64 * b as u64 + ret[i + j] as u64 + carry;
+            ret[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        ret[i + y.len()] = carry as u32;
+    }
+    trim(&mut ret);
+    ret
+}
+
+fn mag_mul_small(x: &mut Digits, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for d in x.iter_mut() {
+        let t = *d as u64 * m as u64 + carry;
+        *d = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        x.push(carry as u32);
+    }
+}
+
+fn mag_divrem_small(x: &[u32], y: u32) -> (Digits, u32) {
+    let mut q = vec![0u32; x.len()];
+    let mut r = 0u64;
+    for i in (0..x.len()).rev() {
+        let t = (r << 32) | x[i] as u64;
+        q[i] = (t / y as u64) as u32;
+        r = t % y as u64;
+    }
+    trim(&mut q);
+    (q, r as u32)
+}
+
+// Knuth, TAOCP vol. 2, 4.3.1, Algorithm D. Requires y to have at least
+// two limbs and |x| >= |y|.
+fn mag_divrem_knuth(x: &[u32], y: &[u32]) -> (Digits, Digits) {
+    let shift = y.last().unwrap().leading_zeros();
+    let shl = |ds: &[u32]| -> Digits {
+        let mut ret = Vec::with_capacity(ds.len() + 1);
+        let mut carry = 0u32;
+        for &d in ds {
+            ret.push((d << shift) | carry);
+            carry = if shift == 0 { 0 } else { d >> (32 - shift) };
+        }
+        ret.push(carry);
+        ret
+    };
+    let mut u = shl(x);
+    let mut v = shl(y);
+    trim(&mut v);
+    let n = v.len();
+    let m = u.len() - n;
+    let mut q = vec![0u32; m];
+    let vtop = v[n - 1] as u64;
+    let vnext = v[n - 2] as u64;
+    for j in (0..m).rev() {
+        let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = num / vtop;
+        let mut rhat = num % vtop;
+        while qhat >= BASE || qhat * vnext > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += vtop;
+            if rhat >= BASE {
+                break;
+            }
+        }
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let p = qhat * v[i] as u64 + carry;
+            carry = p >> 32;
+            let t = u[i + j] as i64 - borrow - (p & 0xffff_ffff) as i64;
+            u[i + j] = t as u32;
+            borrow = if t < 0 { 1 } else { 0 };
+        }
+        let t = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = t as u32;
+        if t < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let s = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = s as u32;
+                carry = s >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        q[j] = qhat as u32;
+    }
+    trim(&mut q);
+    u.truncate(n);
+    let mut r: Digits = Vec::with_capacity(n);
+    for i in 0..n {
+        let hi = if shift == 0 || i + 1 >= n { 0 } else { u[i + 1] << (32 - shift) };
+        r.push((u[i] >> shift) | hi);
+    }
+    trim(&mut r);
+    (q, r)
+}
+
+fn mag_divrem(x: &[u32], y: &[u32]) -> (Digits, Digits) {
+    if mag_cmp(x, y) == Ordering::Less {
+        return (vec![], x.to_vec());
+    }
+    if y.len() == 1 {
+        let (q, r) = mag_divrem_small(x, y[0]);
+        let r = if r == 0 { vec![] } else { vec![r] };
+        return (q, r);
+    }
+    mag_divrem_knuth(x, y)
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut digits: Digits) -> Self {
+        trim(&mut digits);
+        let negative = negative && !digits.is_empty();
+        BigInt { negative, digits }
+    }
+
+    pub fn zero() -> Self {
+        BigInt { negative: false, digits: vec![] }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        let mag = n.unsigned_abs();
+        BigInt::from_parts(n < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let mag = self.digits.iter().rev().fold(0u64, |acc, &d| (acc << 32) | d as u64);
+        if self.negative {
+            if mag <= i64::MAX as u64 + 1 {
+                Some((mag as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if mag <= i64::MAX as u64 {
+            Some(mag as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_i32(&self) -> Option<i32> {
+        self.to_i64().and_then(|n| if n as i32 as i64 == n { Some(n as i32) } else { None })
+    }
+
+    // The digits in `radix`, from 2 to 36, after a minus sign if negative.
+    pub fn to_string_radix(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut chars = vec![];
+        let mut mag = self.digits.clone();
+        while !mag.is_empty() {
+            let (q, r) = mag_divrem_small(&mag, radix);
+            chars.extend(std::char::from_digit(r, radix));
+            mag = q;
+        }
+        if self.negative {
+            chars.push('-');
+        }
+        chars.iter().rev().collect()
+    }
+
+    // Goes through the decimal representation so that the result is
+    // correctly rounded.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    // The number of bits in the magnitude.
+    pub fn bit_len(&self) -> u64 {
+        match self.digits.last() {
+            Some(d) => (self.digits.len() as u64 - 1) * 32 + (32 - d.leading_zeros()) as u64,
+            None => 0
+        }
+    }
+
+    // Multiplies by 2^n.
+    pub fn shl(&self, n: u64) -> Self {
+        let bits = (n % 32) as u32;
+        let mut digits = vec![0; (n / 32) as usize];
+        let mut carry = 0;
+        for &d in &self.digits {
+            let s = ((d as u64) << bits) | carry;
+            digits.push(s as u32);
+            carry = s >> 32;
+        }
+        digits.push(carry as u32);
+        BigInt::from_parts(self.negative, digits)
+    }
+
+    // The integer part of the square root, by Newton's method starting
+    // from a power of two no smaller than the root. Requires self >= 0.
+    pub fn isqrt(&self) -> Self {
+        if self.is_zero() {
+            return BigInt::zero();
+        }
+        let two = BigInt::from_i64(2);
+        let mut x = BigInt::from_i64(1).shl(self.bit_len().div_ceil(2));
+        loop {
+            let (q, _) = self.div_rem(&x).unwrap();
+            let (y, _) = (&x + &q).div_rem(&two).unwrap();
+            if y >= x {
+                return x;
+            }
+            x = y;
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        BigInt { negative: false, digits: self.digits.clone() }
+    }
+
+    // Truncating division, so that the remainder takes the sign of the
+    // dividend. Returns None when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = mag_divrem(&self.digits, &other.digits);
+        Some((BigInt::from_parts(self.negative != other.negative, q),
+              BigInt::from_parts(self.negative, r)))
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut x, mut y) = (self.abs(), other.abs());
+        while !y.is_zero() {
+            let (_, r) = x.div_rem(&y).unwrap();
+            x = y;
+            y = r;
+        }
+        x
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.digits.first().is_none_or(|d| d & 1 == 0)
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let (negative, ds) = match s.as_bytes().first()? {
+            b'-' => (true, &s[1..]),
+            b'+' => (false, &s[1..]),
+            _ => (false, s)
+        };
+        if ds.is_empty() || !ds.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut digits = vec![];
+        for chunk in ds.as_bytes().chunks(9) {
+            let n = chunk.iter().fold(0u32, |acc, &b| acc * 10 + (b - b'0') as u32);
+            mag_mul_small(&mut digits, 10u32.pow(chunk.len() as u32), n);
+        }
+        Some(BigInt::from_parts(negative, digits))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => mag_cmp(&self.digits, &other.digits),
+            (true, true) => mag_cmp(&other.digits, &self.digits)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, mag_add(&self.digits, &other.digits));
+        }
+        match mag_cmp(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, mag_sub(&other.digits, &self.digits))
+            }
+            _ => BigInt::from_parts(self.negative, mag_sub(&self.digits, &other.digits))
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.negative != other.negative,
+                           mag_mul(&self.digits, &other.digits))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut chunks = vec![];
+        let mut mag = self.digits.clone();
+        while !mag.is_empty() {
+            let (q, r) = mag_divrem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn bigint_test() {
+    let big = |s| BigInt::parse(s).expect("valid integer");
+    let fact = (1..=30).fold(BigInt::from_i64(1), |acc, n| &acc * &BigInt::from_i64(n));
+    assert_eq!(fact.to_string(), "265252859812191058636308480000000");
+    assert_eq!(big("-000123").to_string(), "-123");
+    assert_eq!(big("-18446744073709551616").to_string_radix(16), "-10000000000000000");
+    assert_eq!(big("255").to_string_radix(2), "11111111");
+    assert_eq!(big("-0"), BigInt::zero());
+    assert_eq!((&big("18446744073709551616") - &big("1")).to_string(), "18446744073709551615");
+    assert_eq!(&big("-5") + &big("3"), big("-2"));
+    assert_eq!(big("-9223372036854775808").to_i64(), Some(i64::MIN));
+    assert_eq!(big("9223372036854775808").to_i64(), None);
+    assert_eq!(big("2147483648").to_i32(), None);
+
+    let (q, r) = fact.div_rem(&big("-1000000000000000000007")).unwrap();
+    assert_eq!(q.to_string(), "-265252859812");
+    assert_eq!(&(&q * &big("-1000000000000000000007")) + &r, fact);
+    let (q, r) = big("-7").div_rem(&big("2")).unwrap();
+    assert_eq!((q, r), (big("-3"), big("-1")));
+    assert_eq!(big("1").div_rem(&BigInt::zero()), None);
+    assert!(big("-100000000000000000000") < big("-99999999999999999999"));
+    assert_eq!(big("-3").shl(65), big("-110680464442257309696"));
+    assert_eq!(big("4294967295").bit_len(), 32);
+    assert_eq!(big("4294967296").bit_len(), 33);
+    assert_eq!(BigInt::zero().bit_len(), 0);
+    assert_eq!(big("99999999999999999999").isqrt(), big("9999999999"));
+    assert_eq!(big("10000000000000000000000000000000000000000").isqrt(), big("100000000000000000000"));
+    assert_eq!(big("1").isqrt(), big("1"));
+}
+
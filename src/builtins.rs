@@ -0,0 +1,1041 @@
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::result;
+use crate::error::{Error, error};
+use crate::format;
+use crate::hashtable::{Equality, HashTable};
+use crate::module;
+use crate::number::{self, Rounding};
+use crate::object::{self, Native, Object};
+use crate::pmap::PersistentMap;
+use crate::port::Port;
+use crate::pvector::PersistentVector;
+use crate::reader;
+use crate::symbol::Symbol;
+use crate::vm::Vm;
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn math1(args: &[Rc<Object>], f: fn(f64) -> f64) -> Result<Rc<Object>> {
+    Ok(Rc::new(Object::Float(f(number::to_f64(&args[0])?))))
+}
+
+fn round(args: &[Rc<Object>], mode: Rounding) -> Result<Rc<Object>> {
+    Ok(Rc::new(number::round(&args[0], mode)?))
+}
+
+fn convert(args: &[Rc<Object>], f: fn(&Object) -> Result<Object>) -> Result<Rc<Object>> {
+    Ok(Rc::new(f(&args[0])?))
+}
+
+// Same semantics as the inlined forms in the compiler: a left fold, with
+// `identity` as the left operand when given fewer than two arguments.
+fn arith(args: &[Rc<Object>], identity: i32,
+         op: fn(&Object, &Object) -> Result<Object>) -> Result<Rc<Object>> {
+    let (init, rest) = match args {
+        [first, rest @ ..] if !rest.is_empty() => (first.as_ref().clone(), rest),
+        _ => (Object::Number(identity), args)
+    };
+    let mut acc = init;
+    for arg in rest {
+        acc = op(&acc, arg)?;
+    }
+    Ok(Rc::new(acc))
+}
+
+// Every argument is checked to be a number even after the chain fails.
+fn compare(args: &[Rc<Object>], pred: fn(Ordering) -> bool) -> Result<Rc<Object>> {
+    for arg in args {
+        number::to_f64(arg)?;
+    }
+    let mut ret = true;
+    for pair in args.windows(2) {
+        ret &= number::compare(&pair[0], &pair[1])?.is_some_and(pred);
+    }
+    Ok(Rc::new(object::from_bool(ret)))
+}
+
+fn size(obj: &Object) -> Result<usize> {
+    match *obj {
+        Object::Number(n) if n >= 0 => Ok(n as usize),
+        _ => Err(error("Can't be converted to size"))
+    }
+}
+
+fn index(obj: &Object, len: usize) -> Result<usize> {
+    match size(obj) {
+        Ok(i) if i < len => Ok(i),
+        _ => {
+            let msg = format!("Index out of range: {}", obj);
+            Err(error(&msg))
+        }
+    }
+}
+
+fn pairs(args: &[Rc<Object>]) -> Result<impl Iterator<Item = (&Rc<Object>, &Rc<Object>)>> {
+    if !args.len().is_multiple_of(2) {
+        return Err(error("Keys and values must come in pairs"));
+    }
+    Ok(args.chunks(2).map(|kv| (&kv[0], &kv[1])))
+}
+
+fn put(obj: &Object, args: &[Rc<Object>]) -> Result<Rc<Object>> {
+    match obj {
+        Object::Map(m) => {
+            let m = pairs(args)?.fold(m.clone(), |m, (k, v)| m.assoc(k.clone(), v.clone()));
+            Ok(Rc::new(Object::Map(m)))
+        }
+        Object::PVector(v) => {
+            let mut v = v.clone();
+            for (i, obj) in pairs(args)? {
+                v = v.assoc(size(i)?, obj.clone()).ok_or_else(|| {
+                    error(&format!("Index out of range: {}", i))
+                })?;
+            }
+            Ok(Rc::new(Object::PVector(v)))
+        }
+        _ => Err(error("Can't be converted to map or pvector"))
+    }
+}
+
+fn conj(coll: &Rc<Object>, obj: &Rc<Object>) -> Result<Rc<Object>> {
+    match **coll {
+        Object::Nil | Object::Cons(..) => Ok(Rc::new(object::cons(obj.clone(), coll.clone()))),
+        Object::PVector(ref v) => Ok(Rc::new(Object::PVector(v.push(obj.clone())))),
+        Object::Map(ref m) => match **obj {
+            Object::Cons(ref k, ref v) => Ok(Rc::new(Object::Map(m.assoc(k.clone(), v.clone())))),
+            _ => Err(error("Only (key . value) pairs can be added to map"))
+        },
+        _ => Err(error("Can't be converted to collection"))
+    }
+}
+
+fn char_case<I: ExactSizeIterator<Item = char>>(c: char, f: fn(char) -> I) -> Result<Rc<Object>> {
+    let mut mapped = f(c);
+    let c = if mapped.len() == 1 { mapped.next().unwrap() } else { c };
+    Ok(Rc::new(Object::Char(c)))
+}
+
+// Transposes the lists given to `%map`, `for-each` and the folds into
+// argument lists, stopping at the end of the shortest one.
+fn columns(lists: &[Rc<Object>]) -> Result<Vec<Vec<Rc<Object>>>> {
+    let lists = lists.iter().map(|list| object::list_to_vec(list)).collect::<Result<Vec<_>>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    Ok((0..len).map(|i| lists.iter().map(|list| list[i].clone()).collect()).collect())
+}
+
+fn prepend(elems: Vec<Rc<Object>>, tail: Rc<Object>) -> Rc<Object> {
+    elems.into_iter().rev().fold(tail, |acc, elem| Rc::new(object::cons(elem, acc)))
+}
+
+// The first pair in `alist` whose car is equal to `key` by `eq`.
+fn find_pair(key: &Object, alist: &Object, eq: fn(&Object, &Object) -> bool) -> Result<Rc<Object>> {
+    for pair in object::list_to_vec(alist)? {
+        match *pair {
+            Object::Cons(ref k, _) if eq(k, key) => return Ok(pair.clone()),
+            Object::Cons(..) => (),
+            _ => return Err(error("Association list must consist of pairs"))
+        }
+    }
+    Ok(Rc::new(Object::Nil))
+}
+
+// The first tail of `list` whose car is equal to `x` by `eq`.
+fn find_tail(x: &Object, list: &Rc<Object>, eq: fn(&Object, &Object) -> bool) -> Result<Rc<Object>> {
+    let mut tail = list.clone();
+    loop {
+        let next = match *tail {
+            Object::Nil => return Ok(tail),
+            Object::Cons(ref car, _) if eq(car, x) => return Ok(tail),
+            Object::Cons(_, ref cdr) => cdr.clone(),
+            _ => return Err(error("Can't be converted to cons"))
+        };
+        tail = next;
+    }
+}
+
+// Stable merge sort: an element is only put before an earlier one when
+// `less` says it is smaller.
+fn merge_sort(vm: &mut Vm, mut elems: Vec<Rc<Object>>, less: &Object) -> Result<Vec<Rc<Object>>> {
+    if elems.len() <= 1 {
+        return Ok(elems);
+    }
+    let right = elems.split_off(elems.len() / 2);
+    let right = merge_sort(vm, right, less)?;
+    let left = merge_sort(vm, elems, less)?;
+    let mut ret = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if vm.apply(less, vec![r.clone(), l.clone()])?.to_bool() {
+            ret.extend(right.next());
+        } else {
+            ret.extend(left.next());
+        }
+    }
+    ret.extend(left);
+    ret.extend(right);
+    Ok(ret)
+}
+
+// The port argument at `i`, or else the current input or output port.
+fn input_port(vm: &Vm, args: &[Rc<Object>], i: usize) -> Result<Rc<Port>> {
+    args.get(i).map_or(Ok(vm.input_port().clone()), |port| port.to_port().cloned())
+}
+
+fn output_port(vm: &Vm, args: &[Rc<Object>], i: usize) -> Result<Rc<Port>> {
+    args.get(i).map_or(Ok(vm.output_port().clone()), |port| port.to_port().cloned())
+}
+
+fn write_to(vm: &Vm, args: &[Rc<Object>], i: usize, s: &str) -> Result<Rc<Object>> {
+    output_port(vm, args, i)?.write_str(s)?;
+    Ok(Rc::new(Object::Nil))
+}
+
+// Reads a datum, or the eof object at the end of input, along with the
+// char after it as `reader::read_datum` does.
+fn read_one(chars: &mut dyn Iterator<Item = char>) -> (Result<Rc<Object>>, Option<char>) {
+    match reader::read_datum(chars) {
+        (Some(expr), rest) => (expr.map(Rc::new), rest),
+        (None, rest) => (Ok(Rc::new(Object::Eof)), rest)
+    }
+}
+
+const NATIVES: &[Native] = &[
+    Native { name: "+", arity: (0, None), func: |_, args| arith(args, 0, number::add) },
+    Native { name: "-", arity: (1, None), func: |_, args| arith(args, 0, number::sub) },
+    Native { name: "*", arity: (0, None), func: |_, args| arith(args, 1, number::mul) },
+    Native { name: "/", arity: (1, None), func: |_, args| arith(args, 1, number::div) },
+    Native { name: "=", arity: (1, None), func: |_, args| compare(args, Ordering::is_eq) },
+    Native { name: "<", arity: (1, None), func: |_, args| compare(args, Ordering::is_lt) },
+    Native { name: ">", arity: (1, None), func: |_, args| compare(args, Ordering::is_gt) },
+    Native { name: "<=", arity: (1, None), func: |_, args| compare(args, Ordering::is_le) },
+    Native { name: ">=", arity: (1, None), func: |_, args| compare(args, Ordering::is_ge) },
+    // The list primitives are compiled inline when called directly; these
+    // are for passing them as values.
+    Native { name: "cons", arity: (2, Some(2)), func: |_, args| {
+        Ok(Rc::new(object::cons(args[0].clone(), args[1].clone())))
+    }},
+    Native { name: "car", arity: (1, Some(1)), func: |_, args| args[0].car() },
+    Native { name: "cdr", arity: (1, Some(1)), func: |_, args| args[0].cdr() },
+    Native { name: "null", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].is_null())))
+    }},
+    Native { name: "atom", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].is_atom())))
+    }},
+    // The prelude defines Lisp versions of these under the names without
+    // the %, which are not there when the interpreter starts without it.
+    Native { name: "%length", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(number::from_i64(object::list_to_vec(&args[0])?.len() as i64)))
+    }},
+    Native { name: "%reverse", arity: (1, Some(1)), func: |_, args| {
+        let mut elems = object::list_to_vec(&args[0])?;
+        elems.reverse();
+        Ok(Rc::new(object::vec_to_list(&elems)))
+    }},
+    // The last argument is shared, not copied, and need not be a list.
+    Native { name: "%append", arity: (0, None), func: |_, args| {
+        match args.split_last() {
+            None => Ok(Rc::new(Object::Nil)),
+            Some((last, lists)) => lists.iter().rev().try_fold(last.clone(), |acc, list| {
+                Ok(prepend(object::list_to_vec(list)?, acc))
+            })
+        }
+    }},
+    Native { name: "list-tail", arity: (2, Some(2)), func: |_, args| {
+        let mut tail = args[0].clone();
+        for _ in 0..size(&args[1])? {
+            tail = match *tail {
+                Object::Cons(_, ref cdr) => cdr.clone(),
+                _ => return Err(error(&format!("Index out of range: {}", args[1])))
+            };
+        }
+        Ok(tail)
+    }},
+    Native { name: "%map", arity: (2, None), func: |vm, args| {
+        let ret = columns(&args[1..])?.into_iter()
+            .map(|elems| vm.apply(&args[0], elems))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Rc::new(object::vec_to_list(&ret)))
+    }},
+    Native { name: "for-each", arity: (2, None), func: |vm, args| {
+        for elems in columns(&args[1..])? {
+            vm.apply(&args[0], elems)?;
+        }
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "%filter", arity: (2, Some(2)), func: |vm, args| {
+        let mut ret = vec![];
+        for elem in object::list_to_vec(&args[1])? {
+            if vm.apply(&args[0], vec![elem.clone()])?.to_bool() {
+                ret.push(elem);
+            }
+        }
+        Ok(Rc::new(object::vec_to_list(&ret)))
+    }},
+    // (reduce f init '(a b c)) is (f c (f b a)), or init for an empty list.
+    Native { name: "reduce", arity: (3, Some(3)), func: |vm, args| {
+        let elems = object::list_to_vec(&args[2])?;
+        let mut elems = elems.into_iter();
+        let mut acc = match elems.next() {
+            Some(first) => first,
+            None => return Ok(args[1].clone())
+        };
+        for elem in elems {
+            acc = vm.apply(&args[0], vec![elem, acc])?;
+        }
+        Ok(acc)
+    }},
+    // (fold-left f init '(a b)) is (f (f init a) b).
+    Native { name: "fold-left", arity: (3, None), func: |vm, args| {
+        let mut acc = args[1].clone();
+        for mut elems in columns(&args[2..])? {
+            elems.insert(0, acc);
+            acc = vm.apply(&args[0], elems)?;
+        }
+        Ok(acc)
+    }},
+    // (fold-right f init '(a b)) is (f a (f b init)).
+    Native { name: "fold-right", arity: (3, None), func: |vm, args| {
+        let mut acc = args[1].clone();
+        for mut elems in columns(&args[2..])?.into_iter().rev() {
+            elems.push(acc);
+            acc = vm.apply(&args[0], elems)?;
+        }
+        Ok(acc)
+    }},
+    Native { name: "%assoc", arity: (2, Some(2)), func: |_, args| find_pair(&args[0], &args[1], Object::is_equal) },
+    Native { name: "assv", arity: (2, Some(2)), func: |_, args| find_pair(&args[0], &args[1], Object::is_eqv) },
+    Native { name: "assq", arity: (2, Some(2)), func: |_, args| find_pair(&args[0], &args[1], Object::is_eq) },
+    Native { name: "%member", arity: (2, Some(2)), func: |_, args| find_tail(&args[0], &args[1], Object::is_equal) },
+    Native { name: "memv", arity: (2, Some(2)), func: |_, args| find_tail(&args[0], &args[1], Object::is_eqv) },
+    Native { name: "memq", arity: (2, Some(2)), func: |_, args| find_tail(&args[0], &args[1], Object::is_eq) },
+    Native { name: "sort", arity: (2, Some(2)), func: |vm, args| {
+        let sorted = merge_sort(vm, object::list_to_vec(&args[0])?, &args[1])?;
+        Ok(Rc::new(object::vec_to_list(&sorted)))
+    }},
+    Native { name: "exact?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(number::is_exact(&args[0])?)))
+    }},
+    Native { name: "inexact?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(!number::is_exact(&args[0])?)))
+    }},
+    Native { name: "exact->inexact", arity: (1, Some(1)), func: |_, args| {
+        convert(args, number::to_inexact)
+    }},
+    Native { name: "inexact->exact", arity: (1, Some(1)), func: |_, args| {
+        convert(args, number::to_exact)
+    }},
+    Native { name: "numerator", arity: (1, Some(1)), func: |_, args| {
+        convert(args, number::numerator)
+    }},
+    Native { name: "denominator", arity: (1, Some(1)), func: |_, args| {
+        convert(args, number::denominator)
+    }},
+    Native { name: "floor", arity: (1, Some(1)), func: |_, args| round(args, Rounding::Floor) },
+    Native { name: "ceiling", arity: (1, Some(1)), func: |_, args| {
+        round(args, Rounding::Ceiling)
+    }},
+    Native { name: "round", arity: (1, Some(1)), func: |_, args| round(args, Rounding::Round) },
+    Native { name: "truncate", arity: (1, Some(1)), func: |_, args| {
+        round(args, Rounding::Truncate)
+    }},
+    Native { name: "sqrt", arity: (1, Some(1)), func: |_, args| convert(args, number::sqrt) },
+    Native { name: "expt", arity: (2, Some(2)), func: |_, args| {
+        Ok(Rc::new(number::expt(&args[0], &args[1])?))
+    }},
+    Native { name: "exp", arity: (1, Some(1)), func: |_, args| math1(args, f64::exp) },
+    Native { name: "log", arity: (1, Some(2)), func: |_, args| {
+        let x = number::to_f64(&args[0])?;
+        match args.get(1) {
+            Some(base) => Ok(Rc::new(Object::Float(x.ln() / number::to_f64(base)?.ln()))),
+            None => Ok(Rc::new(Object::Float(x.ln())))
+        }
+    }},
+    Native { name: "sin", arity: (1, Some(1)), func: |_, args| math1(args, f64::sin) },
+    Native { name: "cos", arity: (1, Some(1)), func: |_, args| math1(args, f64::cos) },
+    Native { name: "tan", arity: (1, Some(1)), func: |_, args| math1(args, f64::tan) },
+    Native { name: "asin", arity: (1, Some(1)), func: |_, args| math1(args, f64::asin) },
+    Native { name: "acos", arity: (1, Some(1)), func: |_, args| math1(args, f64::acos) },
+    Native { name: "atan", arity: (1, Some(2)), func: |_, args| {
+        let y = number::to_f64(&args[0])?;
+        match args.get(1) {
+            Some(x) => Ok(Rc::new(Object::Float(y.atan2(number::to_f64(x)?)))),
+            None => Ok(Rc::new(Object::Float(y.atan())))
+        }
+    }},
+    Native { name: "eq?", arity: (2, Some(2)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].is_eq(&args[1]))))
+    }},
+    Native { name: "eqv?", arity: (2, Some(2)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].is_eqv(&args[1]))))
+    }},
+    Native { name: "equal?", arity: (2, Some(2)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].is_equal(&args[1]))))
+    }},
+    Native { name: "vector", arity: (0, None), func: |_, args| {
+        Ok(Rc::new(object::vector(args.to_vec())))
+    }},
+    Native { name: "vector?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::Vector(_)))))
+    }},
+    Native { name: "make-vector", arity: (1, Some(2)), func: |_, args| {
+        let fill = args.get(1).cloned().unwrap_or_else(|| Rc::new(Object::Nil));
+        Ok(Rc::new(object::vector(vec![fill; size(&args[0])?])))
+    }},
+    Native { name: "vector-length", arity: (1, Some(1)), func: |_, args| {
+        let len = args[0].to_vector()?.borrow().len();
+        Ok(Rc::new(number::from_i64(len as i64)))
+    }},
+    Native { name: "vector-ref", arity: (2, Some(2)), func: |_, args| {
+        let v = args[0].to_vector()?.borrow();
+        Ok(v[index(&args[1], v.len())?].clone())
+    }},
+    Native { name: "vector-set!", arity: (3, Some(3)), func: |_, args| {
+        let mut v = args[0].to_vector()?.borrow_mut();
+        let i = index(&args[1], v.len())?;
+        v[i] = args[2].clone();
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "vector-fill!", arity: (2, Some(2)), func: |_, args| {
+        for obj in args[0].to_vector()?.borrow_mut().iter_mut() {
+            *obj = args[1].clone();
+        }
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "vector->list", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::vec_to_list(&args[0].to_vector()?.borrow())))
+    }},
+    Native { name: "list->vector", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::vector(object::list_to_vec(&args[0])?)))
+    }},
+    // The vectors are copied first, as `f` may modify them.
+    Native { name: "vector-map", arity: (2, None), func: |vm, args| {
+        let vs = args[1..].iter()
+            .map(|v| Ok(v.to_vector()?.borrow().clone()))
+            .collect::<Result<Vec<_>>>()?;
+        let len = vs.iter().map(Vec::len).min().unwrap_or(0);
+        let mut ret = Vec::with_capacity(len);
+        for i in 0..len {
+            let elems = vs.iter().map(|v| v[i].clone()).collect();
+            ret.push(vm.apply(&args[0], elems)?);
+        }
+        Ok(Rc::new(object::vector(ret)))
+    }},
+    Native { name: "make-hash-table", arity: (0, Some(1)), func: |_, args| {
+        let test = match args.first().map(|arg| arg.as_ref()) {
+            None => Equality::Equal,
+            Some(Object::Symbol(sym)) => match &*sym.name() {
+                "eq" | "eq?" => Equality::Eq,
+                "eqv" | "eqv?" => Equality::Eqv,
+                "equal" | "equal?" => Equality::Equal,
+                _ => return Err(error("hash table test must be one of eq, eqv or equal"))
+            },
+            Some(_) => return Err(error("hash table test must be one of eq, eqv or equal"))
+        };
+        Ok(Rc::new(Object::HashTable(RefCell::new(HashTable::new(test)))))
+    }},
+    Native { name: "hash-table?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::HashTable(_)))))
+    }},
+    Native { name: "hash-ref", arity: (2, Some(3)), func: |_, args| {
+        let v = args[0].to_hash_table()?.borrow().get(&args[1]);
+        Ok(v.or_else(|| args.get(2).cloned()).unwrap_or_else(|| Rc::new(Object::Nil)))
+    }},
+    Native { name: "hash-set!", arity: (3, Some(3)), func: |_, args| {
+        args[0].to_hash_table()?.borrow_mut().insert(args[1].clone(), args[2].clone());
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "hash-remove!", arity: (2, Some(2)), func: |_, args| {
+        args[0].to_hash_table()?.borrow_mut().remove(&args[1]);
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "hash-count", arity: (1, Some(1)), func: |_, args| {
+        let len = args[0].to_hash_table()?.borrow().len();
+        Ok(Rc::new(number::from_i64(len as i64)))
+    }},
+    Native { name: "hash-keys", arity: (1, Some(1)), func: |_, args| {
+        let entries = args[0].to_hash_table()?.borrow().entries();
+        let keys: Vec<_> = entries.into_iter().map(|(k, _)| k).collect();
+        Ok(Rc::new(object::vec_to_list(&keys)))
+    }},
+    Native { name: "hash-values", arity: (1, Some(1)), func: |_, args| {
+        let entries = args[0].to_hash_table()?.borrow().entries();
+        let values: Vec<_> = entries.into_iter().map(|(_, v)| v).collect();
+        Ok(Rc::new(object::vec_to_list(&values)))
+    }},
+    Native { name: "hash->list", arity: (1, Some(1)), func: |_, args| {
+        let entries = args[0].to_hash_table()?.borrow().entries();
+        let pairs: Vec<_> = entries.into_iter().map(|(k, v)| Rc::new(object::cons(k, v))).collect();
+        Ok(Rc::new(object::vec_to_list(&pairs)))
+    }},
+    // Iterates over a snapshot of the entries, so `f` may modify the table.
+    Native { name: "hash-for-each", arity: (2, Some(2)), func: |vm, args| {
+        let entries = args[0].to_hash_table()?.borrow().entries();
+        for (k, v) in entries {
+            vm.apply(&args[1], vec![k, v])?;
+        }
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "pvector", arity: (0, None), func: |_, args| {
+        Ok(Rc::new(Object::PVector(args.iter().cloned().collect())))
+    }},
+    Native { name: "pmap", arity: (0, None), func: |_, args| {
+        put(&Object::Map(PersistentMap::new()), args)
+    }},
+    Native { name: "pvector?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::PVector(_)))))
+    }},
+    Native { name: "pmap?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::Map(_)))))
+    }},
+    // (put coll k v ...) is a copy of the map or pvector with the values
+    // at the keys replaced.
+    Native { name: "put", arity: (3, None), func: |_, args| put(&args[0], &args[1..]) },
+    Native { name: "get", arity: (2, Some(3)), func: |_, args| {
+        let v = match *args[0] {
+            Object::Map(ref m) => m.get(&args[1]),
+            Object::PVector(ref v) => size(&args[1]).ok().and_then(|i| v.get(i).cloned()),
+            _ => return Err(error("Can't be converted to map or pvector"))
+        };
+        Ok(v.or_else(|| args.get(2).cloned()).unwrap_or_else(|| Rc::new(Object::Nil)))
+    }},
+    Native { name: "dissoc", arity: (1, None), func: |_, args| {
+        let m = args[1..].iter().fold(args[0].to_map()?.clone(), |m, k| m.dissoc(k));
+        Ok(Rc::new(Object::Map(m)))
+    }},
+    Native { name: "conj", arity: (1, None), func: |_, args| {
+        let mut coll = args[0].clone();
+        for obj in &args[1..] {
+            coll = conj(&coll, obj)?;
+        }
+        Ok(coll)
+    }},
+    Native { name: "count", arity: (1, Some(1)), func: |_, args| {
+        let len = match *args[0] {
+            Object::Map(ref m) => m.len(),
+            Object::PVector(ref v) => v.len(),
+            Object::Vector(ref v) => v.borrow().len(),
+            Object::HashTable(ref table) => table.borrow().len(),
+            _ => object::list_to_vec(&args[0])?.len()
+        };
+        Ok(Rc::new(number::from_i64(len as i64)))
+    }},
+    Native { name: "contains?", arity: (2, Some(2)), func: |_, args| {
+        let found = match *args[0] {
+            Object::Map(ref m) => m.get(&args[1]).is_some(),
+            Object::PVector(ref v) => size(&args[1]).is_ok_and(|i| i < v.len()),
+            _ => return Err(error("Can't be converted to map or pvector"))
+        };
+        Ok(Rc::new(object::from_bool(found)))
+    }},
+    Native { name: "keys", arity: (1, Some(1)), func: |_, args| {
+        let keys: Vec<_> = args[0].to_map()?.entries().into_iter().map(|(k, _)| k).collect();
+        Ok(Rc::new(object::vec_to_list(&keys)))
+    }},
+    Native { name: "vals", arity: (1, Some(1)), func: |_, args| {
+        let vals: Vec<_> = args[0].to_map()?.entries().into_iter().map(|(_, v)| v).collect();
+        Ok(Rc::new(object::vec_to_list(&vals)))
+    }},
+    Native { name: "pvector->list", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::vec_to_list(&args[0].to_pvector()?.to_vec())))
+    }},
+    Native { name: "list->pvector", arity: (1, Some(1)), func: |_, args| {
+        let v: PersistentVector = object::list_to_vec(&args[0])?.into_iter().collect();
+        Ok(Rc::new(Object::PVector(v)))
+    }},
+    Native { name: "char?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::Char(_)))))
+    }},
+    Native { name: "char->integer", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(number::from_i64(args[0].to_char()? as i64)))
+    }},
+    Native { name: "integer->char", arity: (1, Some(1)), func: |_, args| {
+        let n = args[0].to_number()?;
+        let c = if n >= 0 { char::from_u32(n as u32) } else { None };
+        c.map(|c| Rc::new(Object::Char(c))).ok_or_else(|| {
+            error(&format!("Not a valid character code: {}", n))
+        })
+    }},
+    // Characters whose case mapping is more than one character are left
+    // as they are.
+    Native { name: "char-upcase", arity: (1, Some(1)), func: |_, args| {
+        char_case(args[0].to_char()?, char::to_uppercase)
+    }},
+    Native { name: "char-downcase", arity: (1, Some(1)), func: |_, args| {
+        char_case(args[0].to_char()?, char::to_lowercase)
+    }},
+    Native { name: "char-alphabetic?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_char()?.is_alphabetic())))
+    }},
+    Native { name: "char-numeric?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_char()?.is_numeric())))
+    }},
+    Native { name: "char-whitespace?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_char()?.is_whitespace())))
+    }},
+    // Strings are indexed by character, not by byte.
+    Native { name: "string-ref", arity: (2, Some(2)), func: |_, args| {
+        let s = args[0].to_str()?;
+        let i = index(&args[1], s.chars().count())?;
+        Ok(Rc::new(Object::Char(s.chars().nth(i).unwrap())))
+    }},
+    Native { name: "string->list", arity: (1, Some(1)), func: |_, args| {
+        let chars: Vec<_> = args[0].to_str()?.chars().map(|c| Rc::new(Object::Char(c))).collect();
+        Ok(Rc::new(object::vec_to_list(&chars)))
+    }},
+    Native { name: "list->string", arity: (1, Some(1)), func: |_, args| {
+        let s = object::list_to_vec(&args[0])?.iter()
+            .map(|c| c.to_char())
+            .collect::<Result<String>>()?;
+        Ok(Rc::new(Object::Str(s)))
+    }},
+    Native { name: "symbol->string", arity: (1, Some(1)), func: |_, args| {
+        match *args[0] {
+            Object::Symbol(sym) => Ok(Rc::new(Object::Str(sym.name().to_string()))),
+            _ => Err(error("Can't be converted to symbol"))
+        }
+    }},
+    Native { name: "string->symbol", arity: (1, Some(1)), func: |_, args| {
+        match *args[0] {
+            Object::Str(ref s) => Ok(Rc::new(Object::Symbol(Symbol::intern(s)))),
+            _ => Err(error("Can't be converted to string"))
+        }
+    }},
+    Native { name: "keyword?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::Keyword(_)))))
+    }},
+    Native { name: "keyword->symbol", arity: (1, Some(1)), func: |_, args| {
+        match *args[0] {
+            Object::Keyword(sym) => Ok(Rc::new(Object::Symbol(sym))),
+            _ => Err(error("Can't be converted to keyword"))
+        }
+    }},
+    Native { name: "symbol->keyword", arity: (1, Some(1)), func: |_, args| {
+        match *args[0] {
+            Object::Symbol(sym) => Ok(Rc::new(Object::Keyword(sym))),
+            _ => Err(error("Can't be converted to symbol"))
+        }
+    }},
+    Native { name: "load", arity: (1, Some(1)), func: |vm, args| {
+        let path = args[0].to_str()?.to_string();
+        module::load(vm, Path::new(&path))
+    }},
+    Native { name: "command-line", arity: (0, Some(0)), func: |vm, _| {
+        let args: Vec<_> = vm.command_line().iter().map(|arg| Rc::new(Object::Str(arg.clone()))).collect();
+        Ok(Rc::new(object::vec_to_list(&args)))
+    }},
+    // (apply f a b '(c d)) calls (f a b c d).
+    Native { name: "apply", arity: (2, None), func: |vm, args| {
+        let (list, init) = args[1..].split_last().unwrap();
+        let mut fargs = init.to_vec();
+        fargs.extend(object::list_to_vec(list)?);
+        vm.apply(&args[0], fargs)
+    }},
+    Native { name: "eval", arity: (1, Some(1)), func: |vm, args| vm.eval_nested(&args[0]) },
+    // (error "message" obj ...) fails with the message followed by the
+    // objects as `write` writes them.
+    Native { name: "error", arity: (1, None), func: |_, args| {
+        let mut msg = args[0].to_str()?.to_string();
+        for obj in &args[1..] {
+            msg.push_str(&format!(" {:#}", obj));
+        }
+        Err(error(&msg))
+    }},
+    Native { name: "read", arity: (0, Some(1)), func: |vm, args| {
+        input_port(vm, args, 0)?.read_with(read_one)?
+    }},
+    Native { name: "read-from-string", arity: (1, Some(1)), func: |_, args| {
+        read_one(&mut args[0].to_str()?.chars()).0
+    }},
+    Native { name: "read-char", arity: (0, Some(1)), func: |vm, args| {
+        let c = input_port(vm, args, 0)?.read_char()?;
+        Ok(Rc::new(c.map_or(Object::Eof, Object::Char)))
+    }},
+    Native { name: "read-line", arity: (0, Some(1)), func: |vm, args| {
+        let line = input_port(vm, args, 0)?.read_line()?;
+        Ok(Rc::new(line.map_or(Object::Eof, Object::Str)))
+    }},
+    Native { name: "display", arity: (1, Some(2)), func: |vm, args| {
+        write_to(vm, args, 1, &args[0].to_string())
+    }},
+    Native { name: "write", arity: (1, Some(2)), func: |vm, args| {
+        write_to(vm, args, 1, &format!("{:#}", args[0]))
+    }},
+    Native { name: "newline", arity: (0, Some(1)), func: |vm, args| write_to(vm, args, 0, "\n") },
+    Native { name: "current-input-port", arity: (0, Some(0)), func: |vm, _| {
+        Ok(Rc::new(Object::Port(vm.input_port().clone())))
+    }},
+    Native { name: "current-output-port", arity: (0, Some(0)), func: |vm, _| {
+        Ok(Rc::new(Object::Port(vm.output_port().clone())))
+    }},
+    Native { name: "open-input-file", arity: (1, Some(1)), func: |_, args| {
+        let port = Port::open_input_file(Path::new(args[0].to_str()?))?;
+        Ok(Rc::new(Object::Port(Rc::new(port))))
+    }},
+    Native { name: "open-output-file", arity: (1, Some(1)), func: |_, args| {
+        let port = Port::open_output_file(Path::new(args[0].to_str()?))?;
+        Ok(Rc::new(Object::Port(Rc::new(port))))
+    }},
+    Native { name: "open-input-string", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(Object::Port(Rc::new(Port::input_string(args[0].to_str()?)))))
+    }},
+    Native { name: "open-output-string", arity: (0, Some(0)), func: |_, _| {
+        Ok(Rc::new(Object::Port(Rc::new(Port::output_string()))))
+    }},
+    Native { name: "get-output-string", arity: (1, Some(1)), func: |_, args| {
+        let s = args[0].to_port()?.contents().ok_or_else(|| error("Not a string output port"))?;
+        Ok(Rc::new(Object::Str(s)))
+    }},
+    Native { name: "close-port", arity: (1, Some(1)), func: |_, args| {
+        args[0].to_port()?.close()?;
+        Ok(Rc::new(Object::Nil))
+    }},
+    Native { name: "port?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_port().is_ok())))
+    }},
+    Native { name: "input-port?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_port().is_ok_and(|port| port.is_input()))))
+    }},
+    Native { name: "output-port?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(args[0].to_port().is_ok_and(|port| !port.is_input()))))
+    }},
+    // (format dest ctrl args...) writes to a port, or to the current output
+    // port if dest is t. If dest is nil, or is left out, the output is
+    // returned as a string.
+    Native { name: "format", arity: (1, None), func: |vm, args| {
+        let (dest, args) = match *args[0] {
+            Object::Str(_) => (&Object::Nil, args),
+            _ => (&*args[0], &args[1..])
+        };
+        let (ctrl, args) = args.split_first().ok_or_else(|| error("format needs a control string"))?;
+        let s = format::format(ctrl.to_str()?, args)?;
+        match *dest {
+            Object::Nil => Ok(Rc::new(Object::Str(s))),
+            Object::T => write_to(vm, &[], 0, &s),
+            _ => {
+                dest.to_port()?.write_str(&s)?;
+                Ok(Rc::new(Object::Nil))
+            }
+        }
+    }},
+    // Calls the thunk with output going to a string, which is returned.
+    Native { name: "with-output-to-string", arity: (1, Some(1)), func: |vm, args| {
+        let port = Rc::new(Port::output_string());
+        let prev = vm.set_output_port(port.clone());
+        let ret = vm.apply(&args[0], vec![]);
+        vm.set_output_port(prev);
+        ret?;
+        Ok(Rc::new(Object::Str(port.contents().unwrap_or_default())))
+    }},
+    Native { name: "eof-object", arity: (0, Some(0)), func: |_, _| Ok(Rc::new(Object::Eof)) },
+    Native { name: "eof-object?", arity: (1, Some(1)), func: |_, args| {
+        Ok(Rc::new(object::from_bool(matches!(*args[0], Object::Eof))))
+    }},
+    Native { name: "gensym", arity: (0, Some(1)), func: |_, args| {
+        let prefix = match args.first().map(|arg| arg.as_ref()) {
+            None => "g".to_string(),
+            Some(Object::Str(s)) => s.clone(),
+            Some(Object::Symbol(sym)) => sym.name().to_string(),
+            Some(_) => return Err(error("gensym prefix must be string or symbol"))
+        };
+        Ok(Rc::new(Object::Symbol(Symbol::gensym(&prefix))))
+    }}
+];
+
+pub fn lookup(name: &str) -> Option<Native> {
+    NATIVES.iter().find(|native| native.name == name).cloned()
+}
+
+#[cfg(test)]
+fn eval(src: &str) -> result::Result<String, String> {
+    use crate::{reader, vm::Vm};
+    let expr = reader::read_string(src).expect("no expression").map_err(|e| e.to_string())?;
+    Vm::default().eval(&expr).map(|v| v.to_string()).map_err(|e| e.to_string())
+}
+
+#[test]
+fn math_test() {
+    assert_eq!(eval("(exact? 1)"), Ok("t".to_string()));
+    assert_eq!(eval("(inexact? 1.0)"), Ok("t".to_string()));
+    assert_eq!(eval("(floor -1.5)"), Ok("-2.0".to_string()));
+    assert_eq!(eval("(ceiling 7)"), Ok("7".to_string()));
+    assert_eq!(eval("(round 3.5)"), Ok("4.0".to_string()));
+    assert_eq!(eval("(truncate -1.5)"), Ok("-1.0".to_string()));
+    assert_eq!(eval("(sqrt 16)"), Ok("4".to_string()));
+    assert_eq!(eval("(expt 2 0.5)"), Ok("1.4142135623730951".to_string()));
+    assert_eq!(eval("(log 8 2)"), Ok("3.0".to_string()));
+    assert_eq!(eval("(* 2 (atan 1 0))"), Ok("3.141592653589793".to_string()));
+    assert_eq!(eval("(+ 1 (exp 0))"), Ok("2.0".to_string()));
+    assert_eq!(eval("(sqrt 1 2)"), Err("too many arguments to sqrt".to_string()));
+    assert!(eval("(sin (lambda (x) x))").is_err());
+    assert_eq!(eval("(/ 6 4)"), Ok("3/2".to_string()));
+    assert_eq!(eval("(numerator (/ 6 4))"), Ok("3".to_string()));
+    assert_eq!(eval("(denominator 3/4)"), Ok("4".to_string()));
+    assert_eq!(eval("(exact->inexact 3/4)"), Ok("0.75".to_string()));
+    assert_eq!(eval("(inexact->exact 0.5)"), Ok("1/2".to_string()));
+    assert_eq!(eval("(floor 7/2)"), Ok("3".to_string()));
+}
+
+#[test]
+fn variadic_test() {
+    assert_eq!(eval("(+)"), Ok("0".to_string()));
+    assert_eq!(eval("(*)"), Ok("1".to_string()));
+    assert_eq!(eval("(+ 1 2 3 4)"), Ok("10".to_string()));
+    assert_eq!(eval("(- 5)"), Ok("-5".to_string()));
+    assert_eq!(eval("(- 10 1 2 3)"), Ok("4".to_string()));
+    assert_eq!(eval("(/ 2)"), Ok("1/2".to_string()));
+    assert_eq!(eval("(/ 60 2 3)"), Ok("10".to_string()));
+    assert_eq!(eval("(-)"), Err("too less arguments".to_string()));
+    assert_eq!(eval("(< 1 2 3)"), Ok("t".to_string()));
+    assert_eq!(eval("(< 1 3 2)"), Ok("nil".to_string()));
+    assert_eq!(eval("(>= 3 3 1)"), Ok("t".to_string()));
+    assert_eq!(eval("(= 1 1 (+ 0 1))"), Ok("t".to_string()));
+    assert_eq!(eval("(< 1 (+ 1 1) 2)"), Ok("nil".to_string()));
+    assert_eq!(eval("(< 1)"), Ok("t".to_string()));
+    assert_eq!(eval("(< 2 1 #\\a)"), Err("Can't be converted to number".to_string()));
+    assert_eq!(eval("((lambda (a) (> 3 2 1 a)) 'a)"), Err("Can't be converted to number".to_string()));
+    assert_eq!(eval("((lambda (x y) (< x (+ y 1))) 1 1)"), Ok("t".to_string()));
+    assert_eq!(eval("(+ (if (< 1 2 3) 10 20) 1)"), Ok("11".to_string()));
+    assert_eq!(eval("((lambda (f) (f 1 2 3)) +)"), Ok("6".to_string()));
+    assert_eq!(eval("((lambda (f) (f 10)) -)"), Ok("-10".to_string()));
+    assert_eq!(eval("((lambda (f) (f 1 2 0)) <)"), Ok("nil".to_string()));
+}
+
+#[test]
+fn quote_test() {
+    assert_eq!(eval("'x"), Ok("x".to_string()));
+    assert_eq!(eval("(car (cdr '(1 (2 3))))"), Ok("(2 3)".to_string()));
+    assert!(eval("(quote 1 2)").is_err());
+}
+
+#[test]
+fn symbol_test() {
+    assert_eq!(eval("(symbol->string 'abc)"), Ok("abc".to_string()));
+    assert_eq!(eval("(string->symbol \"abc\")"), Ok("abc".to_string()));
+    assert_eq!(eval("(symbol->string (gensym \"tmp\"))").map(|s| s.starts_with("tmp")),
+               Ok(true));
+    assert!(eval("(symbol->string 1)").is_err());
+    assert_eq!(eval(":name"), Ok(":name".to_string()));
+    assert_eq!(eval("(eq? :a :a)"), Ok("t".to_string()));
+    assert_eq!(eval("(eq? :a 'a)"), Ok("nil".to_string()));
+    assert_eq!(eval("(keyword? :a)"), Ok("t".to_string()));
+    assert_eq!(eval("(keyword? 'a)"), Ok("nil".to_string()));
+    assert_eq!(eval("(keyword->symbol :a)"), Ok("a".to_string()));
+    assert_eq!(eval("(symbol->keyword 'a)"), Ok(":a".to_string()));
+    assert_eq!(eval("(get {:id 3} :id)"), Ok("3".to_string()));
+}
+
+#[test]
+fn equality_test() {
+    assert_eq!(eval("(eq? 'a 'a)"), Ok("t".to_string()));
+    assert_eq!(eval("(eq? '(1) '(1))"), Ok("nil".to_string()));
+    assert_eq!(eval("((lambda (x) (eq? x x)) '(1))"), Ok("t".to_string()));
+    assert_eq!(eval("(eqv? 10000000000 10000000000)"), Ok("t".to_string()));
+    assert_eq!(eval("(eqv? 1/2 0.5)"), Ok("nil".to_string()));
+    assert_eq!(eval("(equal? '(1 (2 \"x\")) '(1 (2 \"x\")))"), Ok("t".to_string()));
+    assert_eq!(eval("(equal? '(1 2) '(1 2 3))"), Ok("nil".to_string()));
+}
+
+#[test]
+fn cons_test() {
+    assert_eq!(eval("(cons 1 (cons 2 nil))"), Ok("(1 2)".to_string()));
+    assert_eq!(eval("(car (cons 'a 'b))"), Ok("a".to_string()));
+    assert_eq!(eval("((lambda (x) (cdr (cons x (+ x 1)))) 1)"), Ok("2".to_string()));
+}
+
+#[test]
+fn vector_test() {
+    assert_eq!(eval("(make-vector 3 0)"), Ok("#(0 0 0)".to_string()));
+    assert_eq!(eval("(vector-ref [1 2 3] 2)"), Ok("3".to_string()));
+    assert_eq!(eval("(vector-ref #(1 2 3) 3)"), Err("Index out of range: 3".to_string()));
+    assert_eq!(eval("(vector-ref #(1 2 3) -1)"), Err("Index out of range: -1".to_string()));
+    assert_eq!(eval("(vector-length (vector 1 2))"), Ok("2".to_string()));
+    assert_eq!(eval("((lambda (v) (cons (vector-set! v 1 'x) v)) [1 2])"),
+               Ok("(nil . #(1 x))".to_string()));
+    assert_eq!(eval("((lambda (v) (cons (vector-fill! v 7) v)) [1 2])"),
+               Ok("(nil . #(7 7))".to_string()));
+    assert_eq!(eval("(vector->list #(1 (2) 3))"), Ok("(1 (2) 3)".to_string()));
+    assert_eq!(eval("(list->vector '(1 2))"), Ok("#(1 2)".to_string()));
+    assert_eq!(eval("(vector-map (lambda (x) (* x x)) #(1 2 3))"), Ok("#(1 4 9)".to_string()));
+    assert_eq!(eval("(vector-map + #(1 2 3) #(10 20))"), Ok("#(11 22)".to_string()));
+    assert_eq!(eval("(equal? #(1 (2)) [1 (2)])"), Ok("t".to_string()));
+}
+
+#[test]
+fn hash_table_test() {
+    let with_table = |test: &str, setup: &str, body: &str| {
+        eval(&format!("((lambda (h) ((lambda (_) {}) {})) (make-hash-table {}))",
+                      body, setup, test))
+    };
+    assert_eq!(with_table("", "(hash-set! h '(1 \"a\") 'x)", "(hash-ref h '(1 \"a\"))"),
+               Ok("x".to_string()));
+    assert_eq!(with_table("'eq", "(hash-set! h '(1) 'x)", "(hash-ref h '(1) 'none)"),
+               Ok("none".to_string()));
+    assert_eq!(with_table("'eq", "(hash-set! h 'k 'x)", "(hash->list h)"),
+               Ok("((k . x))".to_string()));
+    assert_eq!(with_table("'eqv", "(hash-set! h 1/2 'x)", "(hash-ref h 1/2)"),
+               Ok("x".to_string()));
+    assert_eq!(with_table("", "(hash-set! h 1 'x)", "(hash-keys h)"), Ok("(1)".to_string()));
+    assert_eq!(with_table("", "(hash-set! h 1 'x)", "(hash-remove! h 1)"), Ok("nil".to_string()));
+    assert_eq!(with_table("", "(hash-set! h 1 2)",
+                          "((lambda (_) (hash-count h)) (hash-for-each h (lambda (k v) (hash-set! h v k))))"),
+               Ok("2".to_string()));
+    assert!(eval("(make-hash-table 'foo)").is_err());
+}
+
+#[test]
+fn persistent_test() {
+    assert_eq!(eval("(get {a 1 b 2} 'b)"), Ok("2".to_string()));
+    assert_eq!(eval("(get {a 1} 'c 'none)"), Ok("none".to_string()));
+    assert_eq!(eval("(put {} 'a 1)"), Ok("{a 1}".to_string()));
+    assert_eq!(eval("(dissoc (pmap 'a 1 'b 2) 'a 'c)"), Ok("{b 2}".to_string()));
+    assert_eq!(eval("((lambda (m) (get m 'a)) (pmap 'a 1))"), Ok("1".to_string()));
+    assert_eq!(eval("((lambda (m) (pvector (get (put m 'a 2) 'a) (get m 'a))) {a 1})"),
+               Ok("#[2 1]".to_string()));
+    assert_eq!(eval("(conj {a 1} (cons 'a 3))"), Ok("{a 3}".to_string()));
+    assert_eq!(eval("(conj #[1 2] 3 4)"), Ok("#[1 2 3 4]".to_string()));
+    assert_eq!(eval("(conj '(2) 1)"), Ok("(1 2)".to_string()));
+    assert_eq!(eval("(put #[1 2] 0 'x 2 'y)"), Ok("#[x 2 y]".to_string()));
+    assert_eq!(eval("(get #[1 2] 5)"), Ok("nil".to_string()));
+    assert_eq!(eval("(count (list->pvector '(1 2 3)))"), Ok("3".to_string()));
+    assert_eq!(eval("(contains? {nil 1} nil)"), Ok("t".to_string()));
+    assert_eq!(eval("(keys {(1 2) x})"), Ok("((1 2))".to_string()));
+    assert_eq!(eval("(equal? {a 1 b 2} {b 2 a 1})"), Ok("t".to_string()));
+    assert_eq!(eval("(equal? #[1 2] #[1 2])"), Ok("t".to_string()));
+    assert!(eval("(put #[1] 3 'x)").is_err());
+    assert_eq!(eval("(put {a 1} 'a)"), Err("too less arguments to put".to_string()));
+    assert!(eval("(%assoc {a 1} 'a)").is_err());
+    assert!(eval("(pmap 'a)").is_err());
+}
+
+#[test]
+fn char_test() {
+    assert_eq!(eval("(char->integer #\\A)"), Ok("65".to_string()));
+    assert_eq!(eval("(integer->char 955)"), Ok("\u{3bb}".to_string()));
+    assert_eq!(eval("(char-upcase #\\a)"), Ok("A".to_string()));
+    assert_eq!(eval("(char-upcase #\\\u{df})"), Ok("\u{df}".to_string()));
+    assert_eq!(eval("(char-alphabetic? #\\1)"), Ok("nil".to_string()));
+    assert_eq!(eval("(char-numeric? #\\1)"), Ok("t".to_string()));
+    assert_eq!(eval("(string-ref \"a\u{3bb}b\" 1)"), Ok("\u{3bb}".to_string()));
+    assert_eq!(eval("(string->list \"a b\")"), Ok("(a   b)".to_string()));
+    assert_eq!(eval("(list->string (string->list \"abc\"))"), Ok("abc".to_string()));
+    assert_eq!(eval("(eqv? #\\a #\\a)"), Ok("t".to_string()));
+    assert!(eval("(string-ref \"abc\" 3)").is_err());
+    assert!(eval("(integer->char -1)").is_err());
+}
+
+#[test]
+fn define_test() {
+    assert_eq!(eval("(begin (define x 2) (define (sq n) (* n n)) (sq x))"), Ok("4".to_string()));
+    assert_eq!(eval("(begin (define (f) (g)) (define (g) 'g) (f))"), Ok("g".to_string()));
+    assert_eq!(eval("(begin (define vector list->vector) (vector '(1)))"), Ok("#(1)".to_string()));
+    assert_eq!(eval("(define x 1)"), Ok("x".to_string()));
+    assert_eq!(eval("((lambda (x) (define y x) y) 3)"),
+               Err("definitions are only allowed at top level".to_string()));
+    assert_eq!(eval("(begin (define tmp 99) (define (f x) (begin (define tmp (* x 2)) tmp)) tmp)"),
+               Err("definitions are only allowed at top level".to_string()));
+    assert_eq!(eval("(begin (define tmp 99) (if tmp (define tmp 1) nil) tmp)"), Ok("1".to_string()));
+    assert_eq!(eval("(begin)"), Ok("nil".to_string()));
+    assert_eq!(eval("undefined"), Err("unbound variable: undefined".to_string()));
+}
+
+#[test]
+fn record_test() {
+    let with_order = |body: &str| eval(&format!("(begin \
+        (define-record-type <order> (make-order id total) order? \
+          (id order-id) (total order-total set-order-total!) (note order-note)) \
+        {})", body));
+    assert_eq!(with_order("(make-order 3 10)"), Ok("#<order id: 3 total: 10 note: nil>".to_string()));
+    assert_eq!(with_order("(order-total (make-order 3 10))"), Ok("10".to_string()));
+    assert_eq!(with_order("(order? (make-order 3 10))"), Ok("t".to_string()));
+    assert_eq!(with_order("(order? (vector 3 10))"), Ok("nil".to_string()));
+    assert_eq!(with_order("((lambda (o) (begin (set-order-total! o 5) (order-total o))) (make-order 3 10))"),
+               Ok("5".to_string()));
+    assert_eq!(with_order("<order>"), Ok("#<record-type order>".to_string()));
+    assert_eq!(with_order("(order-id 1)"), Err("1 is not a record of type order".to_string()));
+    assert_eq!(eval("(define-record-type point (make-point z) point? (x point-x))"),
+               Err("unknown field: z".to_string()));
+}
+
+#[test]
+fn list_test() {
+    assert_eq!(eval("(%length '(1 2 3))"), Ok("3".to_string()));
+    assert_eq!(eval("(length '(1 2 3))"), Err("unbound variable: length".to_string()));
+    assert_eq!(eval("(%reverse '(1 2 3))"), Ok("(3 2 1)".to_string()));
+    assert_eq!(eval("(%append '(1) nil '(2 3) 4)"), Ok("(1 2 3 . 4)".to_string()));
+    assert_eq!(eval("(list-tail '(1 2 3) 2)"), Ok("(3)".to_string()));
+    assert!(eval("(list-tail '(1 2 3) 4)").is_err());
+    assert_eq!(eval("(%map + '(1 2 3) '(10 20))"), Ok("(11 22)".to_string()));
+    assert_eq!(eval("(%filter (lambda (x) (> x 1)) '(3 1 2))"), Ok("(3 2)".to_string()));
+    assert_eq!(eval("(reduce - 0 '(1 2 3))"), Ok("2".to_string()));
+    assert_eq!(eval("(reduce + 'none nil)"), Ok("none".to_string()));
+    assert_eq!(eval("(fold-left cons nil '(1 2))"), Ok("((nil . 1) . 2)".to_string()));
+    assert_eq!(eval("(fold-right cons nil '(1 2))"), Ok("(1 2)".to_string()));
+    assert_eq!(eval("(fold-left (lambda (acc x y) (+ acc (* x y))) 0 '(1 2) '(3 4))"), Ok("11".to_string()));
+    assert_eq!(eval("(%assoc \"b\" '((\"a\" . 1) (\"b\" . 2)))"), Ok("(b . 2)".to_string()));
+    assert_eq!(eval("(assq 'c '((a . 1)))"), Ok("nil".to_string()));
+    assert_eq!(eval("(memq 'c '(a b c d))"), Ok("(c d)".to_string()));
+    assert_eq!(eval("(%member '(1) '(a (1) b))"), Ok("((1) b)".to_string()));
+    assert_eq!(eval("(sort '(3 1 2 5 4) <)"), Ok("(1 2 3 4 5)".to_string()));
+    assert_eq!(eval("(sort '((1 . a) (0 . b) (1 . c) (0 . d)) (lambda (x y) (< (car x) (car y))))"),
+               Ok("((0 . b) (0 . d) (1 . a) (1 . c))".to_string()));
+    assert!(eval("(sort '(1 a) <)").is_err());
+    assert_eq!(eval("(%length (%map (lambda (x) x) (vector->list (make-vector 100000 0))))"),
+               Ok("100000".to_string()));
+    assert_eq!(eval("((lambda (x) (equal? x (%map (lambda (y) y) x))) (vector->list (make-vector 100000 0)))"),
+               Ok("t".to_string()));
+    assert_eq!(eval("(%length (read-from-string (format nil \"~s\" (vector->list (make-vector 100000 'a)))))"),
+               Ok("100000".to_string()));
+    assert_eq!(eval("((lambda (xs f) (equal? (fold-left f nil xs) (fold-left f nil xs))) \
+                      (vector->list (make-vector 100000 0)) (lambda (acc x) (cons acc nil)))"),
+               Ok("t".to_string()));
+}
+
+#[test]
+fn apply_eval_read_test() {
+    assert_eq!(eval("(apply + 1 2 '(3 4))"), Ok("10".to_string()));
+    assert_eq!(eval("(apply (lambda xs xs) nil)"), Ok("nil".to_string()));
+    assert!(eval("(apply car '(1 2))").is_err());
+    assert_eq!(eval("(eval (cons '* '(6 7)))"), Ok("42".to_string()));
+    assert_eq!(eval("(begin (eval '(define x 5)) x)"), Ok("5".to_string()));
+    assert_eq!(eval("(eval (read-from-string \"((lambda (x) (* x x)) 3) ignored\"))"), Ok("9".to_string()));
+    assert_eq!(eval("(read-from-string \"[1 2]\")"), Ok("#(1 2)".to_string()));
+    assert_eq!(eval("(eof-object? (read-from-string \" ; nothing\"))"), Ok("t".to_string()));
+    assert!(eval("(read-from-string \"(1 2\")").is_err());
+    assert_eq!(eval("(error \"bad thing:\" 'x \"y\" 1)"), Err("bad thing: x \"y\" 1".to_string()));
+    assert_eq!(eval("(error \"no irritants\")"), Err("no irritants".to_string()));
+}
+
+#[test]
+fn port_test() {
+    assert_eq!(eval("(with-output-to-string (lambda () (display \"a\") (write \"b\") (newline) (write '(#\\c \"d\"))))"),
+               Ok("a\"b\"\n(#\\c \"d\")".to_string()));
+    assert_eq!(eval("((lambda (p) (display 42 p) (write 'x p) (get-output-string p)) (open-output-string))"),
+               Ok("42x".to_string()));
+    assert_eq!(eval("(begin (define p (open-input-string \"ab c\n(1 2) \")) \
+                            (define a (read-char p)) (define b (read-line p)) (define c (read p)) \
+                            (define d (read-line p)) (define e (read-line p)) \
+                            (with-output-to-string (lambda () (write (vector a b c d e)))))"),
+               Ok("#(#\\a \"b c\" (1 2) \" \" #<eof>)".to_string()));
+    assert_eq!(eval("(begin (define p (open-input-string \"a(b c) x\ny\")) \
+                            (define a (read p)) (define b (read p)) (define c (read p)) \
+                            (define d (read-char p)) (define e (read p)) (define f (read-line p)) \
+                            (with-output-to-string (lambda () (write (vector a b c d e f)))))"),
+               Ok("#(a (b c) x #\\newline y #<eof>)".to_string()));
+    assert_eq!(eval("((lambda (p) (close-port p) (read-char p)) (open-input-string \"a\"))"),
+               Err("Port is closed".to_string()));
+    assert!(eval("(with-output-to-string (lambda () (car 1)))").is_err());
+    assert_eq!(eval("(vector (input-port? (current-input-port)) (output-port? (current-input-port)))"),
+               Ok("#(t nil)".to_string()));
+    assert!(eval("(display 1 (current-input-port))").is_err());
+    assert_eq!(eval("(format \"~a-~3,'0d\" 'x 7)"), Ok("x-007".to_string()));
+    assert_eq!(eval("(with-output-to-string (lambda () (format t \"~s\" \"a\") (format nil \"b\")))"),
+               Ok("\"a\"".to_string()));
+    assert!(eval("(format 1 \"x\")").is_err());
+    assert_eq!(eval("((lambda (p) (format p \"~{~a~}\" '(1 2)) (get-output-string p)) (open-output-string))"),
+               Ok("12".to_string()));
+}
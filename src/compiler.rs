@@ -1,29 +1,36 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::result;
+use crate::builtins;
 use crate::env::Location;
 use crate::error::{Error, error};
 use crate::insns::{Code, Insn};
 use crate::insns::Insn::*;
+use crate::module;
+use crate::namespace::Namespace;
 use crate::object::{self, Object};
 use crate::object::Object::*;
+use crate::record;
+use crate::symbol;
 
 pub type Result<T> = result::Result<T, Error>;
-type CEnv = HashMap<String, Location>;
+type CEnv = HashMap<symbol::Symbol, Location>;
 
 #[derive(Debug, Clone)]
-struct Compiler {
+struct Compiler<'a> {
     insns: Vec<Insn>,
-    cenv: CEnv,
-    level: usize
+    cenv: Rc<CEnv>,
+    level: usize,
+    ns: &'a Namespace
 }
 
-impl Compiler {
-    fn new() -> Self {
+impl<'a> Compiler<'a> {
+    fn new(ns: &'a Namespace) -> Self {
         Compiler {
             insns: Vec::new(),
-            cenv: HashMap::new(),
-            level: 0
+            cenv: Rc::new(HashMap::new()),
+            level: 0,
+            ns
         }
     }
 
@@ -31,70 +38,86 @@ impl Compiler {
         Compiler {
             insns: Vec::new(),
             cenv: self.cenv.clone(),
-            level: self.level
+            level: self.level,
+            ns: self.ns
         }
     }
 
     fn compile(&mut self, expr: &Object) -> Result<()> {
         match expr {
             Nil => self.insns.push(Inil),
-            T | Number(_) => {
+            Symbol(sym) => self.compile_symbol(*sym)?,
+            Cons(car, cdr) => self.compile_list(car, cdr)?,
+            _ => {
                 let obj = expr.clone();
                 self.insns.push(Ildc(Rc::new(obj)));
             }
-            Symbol(ref name) => {
-                let (i, j) = self.cenv.get(name).ok_or_else(|| {
-                    let msg = format!("unknown variable: {}", *name);
-                    error(&msg)
-                })?;
-                self.insns.push(Ild((self.level - i, *j)));
-            }
-            Cons(car, cdr) => self.compile_list(car, cdr)?,
-            _ => unimplemented!()
+        }
+        Ok(())
+    }
+
+    // Variables not bound by an enclosing lambda are globals, which are
+    // checked to be defined only when they are evaluated.
+    fn compile_symbol(&mut self, sym: symbol::Symbol) -> Result<()> {
+        if let Some((i, j)) = self.cenv.get(&sym) {
+            self.insns.push(Ild((self.level - i, *j)));
+        } else {
+            self.insns.push(Igld(self.ns.global(sym)));
         }
         Ok(())
     }
 
     fn compile_list(&mut self, car: &Object, cdr: &Object) -> Result<()> {
         match car {
-            Symbol(ref name) => {
-                match name.as_ref() {
-                    "+"  => self.compile_op(2, cdr, Iadd)?,
-                    "-"  => self.compile_op(2, cdr, Isub)?,
-                    "*"  => self.compile_op(2, cdr, Imul)?,
-                    "/"  => self.compile_op(2, cdr, Idiv)?,
-                    "="  => self.compile_op(2, cdr, Ieq )?,
-                    "<"  => self.compile_op(2, cdr, Ilt )?,
-                    ">"  => self.compile_op(2, cdr, Igt )?,
-                    "<=" => self.compile_op(2, cdr, Ilte)?,
-                    ">=" => self.compile_op(2, cdr, Igte)?,
-                    "cons" => self.compile_op(2, cdr, Icons)?,
-                    "car"  => self.compile_op(1, cdr, Icar)?,
-                    "cdr"  => self.compile_op(1, cdr, Icdr)?,
-                    "null" => self.compile_op(1, cdr, Inull)?,
-                    "atom" => self.compile_op(1, cdr, Iatom)?,
-                    "if" => self.compile_if(cdr)?,
-                    "lambda" => self.compile_lambda(cdr)?,
+            Symbol(sym) => {
+                match *sym {
+                    symbol::ADD => self.compile_arith(0, 0, cdr, Iadd)?,
+                    symbol::SUB => self.compile_arith(1, 0, cdr, Isub)?,
+                    symbol::MUL => self.compile_arith(0, 1, cdr, Imul)?,
+                    symbol::DIV => self.compile_arith(1, 1, cdr, Idiv)?,
+                    symbol::EQ => self.compile_compare(*sym, cdr, Ieq )?,
+                    symbol::LT => self.compile_compare(*sym, cdr, Ilt )?,
+                    symbol::GT => self.compile_compare(*sym, cdr, Igt )?,
+                    symbol::LE => self.compile_compare(*sym, cdr, Ilte)?,
+                    symbol::GE => self.compile_compare(*sym, cdr, Igte)?,
+                    symbol::CONS => self.compile_cons(cdr)?,
+                    symbol::CAR  => self.compile_op(1, cdr, Icar)?,
+                    symbol::CDR  => self.compile_op(1, cdr, Icdr)?,
+                    symbol::NULL => self.compile_op(1, cdr, Inull)?,
+                    symbol::ATOM => self.compile_op(1, cdr, Iatom)?,
+                    symbol::QUOTE => self.compile_quote(cdr)?,
+                    symbol::IF => self.compile_if(cdr)?,
+                    symbol::LAMBDA => self.compile_lambda(cdr)?,
+                    symbol::BEGIN => self.compile_begin(cdr)?,
+                    symbol::DEFINE => self.compile_define(cdr)?,
+                    symbol::DEFINE_RECORD_TYPE => self.compile_record_type(cdr)?,
+                    symbol::MODULE => self.compile_module(cdr)?,
+                    symbol::IMPORT => self.compile_import(cdr)?,
                     _ => self.compile_application(car, cdr)?
                 }
             }
-            Nil | T | Number(_) => {
+            Cons(..) | Native(_) => self.compile_application(car, cdr)?,
+            _ => {
                 let msg = format!("{} is not applicable", *car);
                 return Err(error(&msg));
             }
-            _ => self.compile_application(car, cdr)?
         }
         Ok(())
     }
 
-    fn take_args(&self, n: usize, args: &Object) -> Result<Vec<Rc<Object>>> {
-        let args = object::list_to_vec(args).or_else(|_| {
-            Err(error("arglist must be proper list"))
+    fn take_min_args(&self, n: usize, args: &Object) -> Result<Vec<Rc<Object>>> {
+        let args = object::list_to_vec(args).map_err(|_| {
+            error("arglist must be proper list")
         })?;
-        let nargs = args.len();
-        if nargs < n {
+        if args.len() < n {
             return Err(error("too less arguments"))
-        } else if nargs > n {
+        }
+        Ok(args)
+    }
+
+    fn take_args(&self, n: usize, args: &Object) -> Result<Vec<Rc<Object>>> {
+        let args = self.take_min_args(n, args)?;
+        if args.len() > n {
             return Err(error("too many arguments"))
         }
         Ok(args)
@@ -109,30 +132,251 @@ impl Compiler {
         Ok(())
     }
 
+    // Folds the arguments from the left with a binary instruction. With
+    // fewer than two arguments, `identity` becomes the left operand, which
+    // makes (- x) a negation and (/ x) a reciprocal.
+    fn compile_arith(&mut self, min: usize, identity: i32, args: &Object, insn: Insn) -> Result<()> {
+        let args = self.take_min_args(min, args)?;
+        if args.len() < 2 {
+            self.insns.push(Ildc(Rc::new(Number(identity))));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.compile(arg.as_ref())?;
+            if i > 0 || args.len() == 1 {
+                self.insns.push(insn.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // Chains comparisons as (and (< a b) (< b c) ...). Arguments after the
+    // first get compiled twice, so a chain of more than two arguments is
+    // only inlined when they are atoms; otherwise the native is called.
+    fn compile_compare(&mut self, name: symbol::Symbol, args: &Object, insn: Insn) -> Result<()> {
+        let args = self.take_min_args(1, args)?;
+        if args.len() == 2 || (args.len() > 2 && args[1..].iter().all(|arg| arg.is_atom())) {
+            return self.compile_chain(&args, insn);
+        }
+        let native = builtins::lookup(&name.name()).expect("comparison must have a native");
+        self.compile_application(&Native(native), &object::vec_to_list(&args))
+    }
+
+    // The comparisons after a false one are still made and their results
+    // dropped, so that every argument is checked to be a number as the
+    // native does.
+    fn compile_chain(&mut self, args: &[Rc<Object>], insn: Insn) -> Result<()> {
+        self.compile(args[0].as_ref())?;
+        self.compile(args[1].as_ref())?;
+        self.insns.push(insn.clone());
+        if args.len() > 2 {
+            let mut c1 = self.renew();
+            c1.compile_chain(&args[1..], insn.clone())?;
+            c1.insns.push(Ijoin);
+            let mut c2 = self.renew();
+            for pair in args[1..].windows(2) {
+                c2.compile(pair[0].as_ref())?;
+                c2.compile(pair[1].as_ref())?;
+                c2.insns.push(insn.clone());
+                c2.insns.push(Ipop);
+            }
+            c2.insns.extend([Inil, Ijoin]);
+            self.insns.push(Isel(Rc::new(c1.insns), Rc::new(c2.insns)));
+        }
+        Ok(())
+    }
+
+    // Icons takes the car from the top of the stack, so the arguments are
+    // evaluated right to left, as in applications.
+    fn compile_cons(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_args(2, args)?;
+        self.compile(args[1].as_ref())?;
+        self.compile(args[0].as_ref())?;
+        self.insns.push(Icons);
+        Ok(())
+    }
+
+    fn compile_quote(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_args(1, args)?;
+        self.insns.push(Ildc(args[0].clone()));
+        Ok(())
+    }
+
     fn compile_if(&mut self, args: &Object) -> Result<()> {
         let args = self.take_args(3, args)?;
         self.compile(args[0].as_ref())?;
         let mut c1 = self.renew();
         c1.compile(args[1].as_ref())?;
+        c1.insns.push(Ijoin);
         let mut c2 = self.renew();
         c2.compile(args[2].as_ref())?;
+        c2.insns.push(Ijoin);
         self.insns.push(Isel(Rc::new(c1.insns), Rc::new(c2.insns)));
         Ok(())
     }
 
+    // Evaluates the forms in order, discarding all values but the last.
+    fn compile_body(&mut self, body: &[Rc<Object>]) -> Result<()> {
+        if body.is_empty() {
+            self.insns.push(Inil);
+        }
+        for (i, expr) in body.iter().enumerate() {
+            if i > 0 {
+                self.insns.push(Ipop);
+            }
+            self.compile(expr.as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn compile_begin(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_min_args(0, args)?;
+        self.compile_body(&args)
+    }
+
+    // (define name expr) or (define (name params ...) body ...). Both
+    // define a global and evaluate to its name.
+    fn compile_define(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_min_args(2, args)?;
+        match args[0].as_ref() {
+            Symbol(name) => {
+                if args.len() > 2 {
+                    return Err(error("too many arguments"));
+                }
+                self.compile_global(*name, &args[1])
+            }
+            Cons(name, params) => {
+                let name = symbol_of(name, "function name must be symbol")?;
+                let mut lambda = vec![Rc::new(Symbol(symbol::LAMBDA)), params.clone()];
+                lambda.extend_from_slice(&args[1..]);
+                self.compile_global(name, &object::vec_to_list(&lambda))
+            }
+            _ => Err(error("variable name must be symbol"))
+        }
+    }
+
+    // Definitions are only allowed outside lambdas, as a definition in a
+    // lambda body would otherwise set a global rather than a local.
+    fn compile_global(&mut self, name: symbol::Symbol, expr: &Object) -> Result<()> {
+        if self.level > 0 {
+            return Err(error("definitions are only allowed at top level"));
+        }
+        self.compile(expr)?;
+        self.insns.push(Idef(self.ns.global(name)));
+        Ok(())
+    }
+
+    // (define-record-type <name> (constructor field ...) predicate
+    //   (field accessor [modifier]) ...)
+    // defines the type, constructor, predicate, accessors and modifiers
+    // as globals, and evaluates to the type name.
+    fn compile_record_type(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_min_args(3, args)?;
+        let type_name = symbol_of(&args[0], "record type name must be symbol")?;
+        let specs = args[3..].iter()
+            .map(|spec| {
+                let spec = object::list_to_vec(spec).ok()
+                    .filter(|spec| spec.len() == 2 || spec.len() == 3)
+                    .ok_or_else(|| error("field spec must be (field accessor [modifier])"))?;
+                spec.iter().map(|s| symbol_of(s, "field spec must consist of symbols")).collect()
+            })
+            .collect::<Result<Vec<Vec<symbol::Symbol>>>>()?;
+        let fields: Vec<_> = specs.iter().map(|spec| spec[0]).collect();
+        let ctor = object::list_to_vec(&args[1])
+            .map_err(|_| error("constructor spec must be (constructor field ...)"))?;
+        let ctor_name = symbol_of(ctor.first().ok_or(error("constructor spec must not be empty"))?,
+                                  "constructor name must be symbol")?;
+        let ctor_fields = ctor[1..].iter()
+            .map(|field| {
+                let field = symbol_of(field, "field name must be symbol")?;
+                fields.iter().position(|f| *f == field).ok_or_else(|| {
+                    error(&format!("unknown field: {}", field))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let rtd = Rc::new(RecordType(Rc::new(record::RecordType::new(type_name, fields, ctor_fields))));
+
+        let sym = |name: &str| Rc::new(object::symbol(name));
+        let lambda = |params: Vec<Rc<Object>>, body: Vec<Rc<Object>>| {
+            object::vec_to_list(&[
+                Rc::new(Symbol(symbol::LAMBDA)),
+                Rc::new(object::vec_to_list(&params)),
+                Rc::new(object::vec_to_list(&body))
+            ])
+        };
+        let native = |native| Rc::new(Native(native));
+        let mut defs = vec![(type_name, Object::clone(&rtd))];
+        let params = ctor[1..].to_vec();
+        let mut body = vec![native(record::CONSTRUCTOR), rtd.clone()];
+        body.extend(params.iter().cloned());
+        defs.push((ctor_name, lambda(params, body)));
+        let pred = symbol_of(&args[2], "predicate name must be symbol")?;
+        defs.push((pred, lambda(vec![sym("obj")], vec![native(record::PREDICATE), rtd.clone(), sym("obj")])));
+        for (i, spec) in specs.iter().enumerate() {
+            let i = Rc::new(Number(i as i32));
+            let body = vec![native(record::ACCESSOR), rtd.clone(), i.clone(), sym("obj")];
+            defs.push((spec[1], lambda(vec![sym("obj")], body)));
+            if let Some(modifier) = spec.get(2) {
+                let body = vec![native(record::MODIFIER), rtd.clone(), i, sym("obj"), sym("value")];
+                defs.push((*modifier, lambda(vec![sym("obj"), sym("value")], body)));
+            }
+        }
+        for (name, expr) in defs {
+            self.compile_global(name, &expr)?;
+            self.insns.push(Ipop);
+        }
+        self.insns.push(Ildc(Rc::new(Symbol(type_name))));
+        Ok(())
+    }
+
+    fn compile_module(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_min_args(2, args)?;
+        let body = object::vec_to_list(&args[2..]);
+        let args = [args[0].clone(), args[1].clone(), Rc::new(body)];
+        self.compile_quoted_call(module::MODULE, &args)
+    }
+
+    fn compile_import(&mut self, args: &Object) -> Result<()> {
+        let args = self.take_args(1, args)?;
+        self.compile_quoted_call(module::IMPORT, &args)
+    }
+
+    fn compile_quoted_call(&mut self, native: object::Native, args: &[Rc<Object>]) -> Result<()> {
+        self.insns.push(Inil);
+        for arg in args.iter().rev() {
+            self.insns.push(Ildc(arg.clone()));
+            self.insns.push(Icons);
+        }
+        self.insns.push(Ildc(Rc::new(Native(native))));
+        self.insns.push(Iap);
+        Ok(())
+    }
+
     fn compile_lambda(&mut self, args: &Object) -> Result<()> {
-        let args = self.take_args(2, args)?;
+        let args = self.take_min_args(2, args)?;
         let mut c = self.renew();
         c.level += 1;
-        for (i, arg) in object::list_to_vec(args[0].as_ref())?.iter().enumerate() {
-            match arg.as_ref() {
-                Symbol(ref name) => {
-                    c.cenv.insert(name.to_owned(), (c.level, i));
-                }
-                _ => return Err(error("fn argument must be symbol"))
+        // (a b . rest) or rest alone takes the remaining arguments as a
+        // list.
+        let mut params = vec![];
+        let mut tail = args[0].as_ref();
+        while let Cons(param, rest) = tail {
+            params.push(symbol_of(param, "fn argument must be symbol")?);
+            tail = rest;
+        }
+        let rest = match tail {
+            Nil => false,
+            Symbol(sym) => {
+                params.push(*sym);
+                true
             }
+            _ => return Err(error("fn argument must be symbol"))
+        };
+        for (i, sym) in params.iter().enumerate() {
+            Rc::make_mut(&mut c.cenv).insert(*sym, (c.level, i));
         }
-        c.compile(args[1].as_ref())?;
+        let required = if rest { params.len() - 1 } else { params.len() };
+        c.insns.push(Iargs(required, rest));
+        c.compile_body(&args[1..])?;
         c.insns.push(Irtn);
         self.insns.push(Ildf(Rc::new(c.insns)));
         Ok(())
@@ -151,8 +395,15 @@ impl Compiler {
     }
 }
 
-pub fn compile(expr: &Object) -> Result<Code> {
-    let mut compiler = Compiler::new();
+fn symbol_of(obj: &Object, msg: &str) -> Result<symbol::Symbol> {
+    match *obj {
+        Symbol(sym) => Ok(sym),
+        _ => Err(error(msg))
+    }
+}
+
+pub fn compile(expr: &Object, ns: &Namespace) -> Result<Code> {
+    let mut compiler = Compiler::new(ns);
     compiler.compile(expr)?;
     Ok(Rc::new(compiler.insns))
 }
@@ -161,10 +412,10 @@ pub fn compile(expr: &Object) -> Result<Code> {
 fn compile_test() {
     // (+ (* 3 3) (* 4 4))
     let code = compile(&Cons(
-        Rc::new(Symbol("+".to_owned())),
+        Rc::new(object::symbol("+")),
         Rc::new(Cons(
             Rc::new(Cons(
-                Rc::new(Symbol("*".to_owned())),
+                Rc::new(object::symbol("*")),
                 Rc::new(Cons(
                     Rc::new(Number(3)),
                     Rc::new(Cons(
@@ -175,7 +426,7 @@ fn compile_test() {
             )),
             Rc::new(Cons(
                 Rc::new(Cons(
-                    Rc::new(Symbol("*".to_owned())),
+                    Rc::new(object::symbol("*")),
                     Rc::new(Cons(
                         Rc::new(Number(4)),
                         Rc::new(Cons(
@@ -187,7 +438,7 @@ fn compile_test() {
                 Rc::new(Nil)
             ))
         ))
-    )).expect("compile fails");
+    ), &Namespace::new()).expect("compile fails");
     let expected = Rc::new(vec![
         Ildc(Rc::new(Number(3))),
         Ildc(Rc::new(Number(3))),
@@ -199,3 +450,18 @@ fn compile_test() {
     ]);
     assert_eq!(code, expected);
 }
+
+#[test]
+fn compare_test() {
+    // (< 1 (+ 2 3)) is inlined even though an argument is not an atom.
+    let expr = crate::reader::read_string("(< 1 (+ 2 3))").unwrap().unwrap();
+    let code = compile(&expr, &Namespace::new()).expect("compile fails");
+    let expected = Rc::new(vec![
+        Ildc(Rc::new(Number(1))),
+        Ildc(Rc::new(Number(2))),
+        Ildc(Rc::new(Number(3))),
+        Iadd,
+        Ilt
+    ]);
+    assert_eq!(code, expected);
+}
@@ -15,6 +15,12 @@ pub type Location = (usize, usize);
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl Default for Env {
+    fn default() -> Self {
+        Env::new()
+    }
+}
+
 impl Env {
     pub fn new() -> Self {
         Env::Empty
@@ -32,7 +38,7 @@ impl Env {
         let mut frame: Option<&Frame> = None;
         for _ in 0..i+1 {
             match env {
-                &Env::Frame(ref f, ref next) => {
+                Env::Frame(f, next) => {
                     frame = Some(f);
                     env = next;
                 }
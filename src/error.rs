@@ -3,11 +3,24 @@ use std::error;
 
 #[derive(Debug, Clone)]
 pub struct Error {
-    message: String
+    message: String,
+    incomplete: bool
 }
 
 pub fn error(message: &str) -> Error {
-    Error { message: message.to_owned() }
+    Error { message: message.to_owned(), incomplete: false }
+}
+
+// An error caused only by the input ending too early, which more input
+// could fix.
+pub fn incomplete(message: &str) -> Error {
+    Error { message: message.to_owned(), incomplete: true }
+}
+
+impl Error {
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
 }
 
 impl fmt::Display for Error {
@@ -0,0 +1,159 @@
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::result;
+use crate::bigint::BigInt;
+use crate::error::{Error, error};
+use crate::object::{self, Object};
+
+pub type Result<T> = result::Result<T, Error>;
+
+// A directive is `~`, then optionally a minimum width and `,'c` for the
+// padding char, optionally `@`, and then one of:
+//
+//   ~a  display    ~d  decimal        ~%  newline
+//   ~s  write      ~x  hexadecimal    ~~  a tilde
+//                  ~b  binary         ~{ body ~}  the body for each
+//                  ~o  octal              element of a list argument
+//
+// Values are padded on the right by ~a and ~s, and on the left by the
+// others; `~@a` and `~@s` pad on the left, and `~@d` prints a plus sign
+// for non-negative numbers. Non-integers are printed by ~d and the like
+// as by ~a.
+pub fn format(ctrl: &str, args: &[Rc<Object>]) -> Result<String> {
+    let ctrl: Vec<char> = ctrl.chars().collect();
+    let mut out = String::new();
+    run(&ctrl, &mut args.iter().cloned().peekable(), &mut out)?;
+    Ok(out)
+}
+
+struct Directive {
+    width: usize,
+    pad: char,
+    at: bool,
+    kind: char
+}
+
+fn next_arg(args: &mut impl Iterator<Item = Rc<Object>>) -> Result<Rc<Object>> {
+    args.next().ok_or_else(|| error("Not enough arguments for format"))
+}
+
+// Parses the directive after the `~` at `ctrl[0]`, returning it with the
+// number of chars it takes.
+fn parse(ctrl: &[char]) -> Result<(Directive, usize)> {
+    let mut i = 1;
+    let digits: String = ctrl[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+    i += digits.len();
+    let width = digits.parse().unwrap_or(0);
+    let mut pad = ' ';
+    if ctrl.get(i) == Some(&',') && ctrl.get(i + 1) == Some(&'\'') {
+        pad = *ctrl.get(i + 2).ok_or_else(|| error("Missing padding char in format"))?;
+        i += 3;
+    }
+    let at = ctrl.get(i) == Some(&'@');
+    if at {
+        i += 1;
+    }
+    let kind = ctrl.get(i).ok_or_else(|| error("Unterminated format directive"))?;
+    Ok((Directive { width, pad, at, kind: kind.to_ascii_lowercase() }, i + 1))
+}
+
+// The position of the `~}` closing the `~{` that ends just before `ctrl`.
+fn find_close(ctrl: &[char]) -> Result<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < ctrl.len() {
+        if ctrl[i] == '~' {
+            let (d, len) = parse(&ctrl[i..])?;
+            match d.kind {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(i),
+                '}' => depth -= 1,
+                _ => ()
+            }
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+    Err(error("Missing ~} in format"))
+}
+
+fn pad(out: &mut String, s: &str, d: &Directive, left: bool) {
+    let fill: String = std::iter::repeat_n(d.pad, d.width.saturating_sub(s.chars().count())).collect();
+    if left {
+        out.push_str(&fill);
+        out.push_str(s);
+    } else {
+        out.push_str(s);
+        out.push_str(&fill);
+    }
+}
+
+fn integer(obj: &Object, radix: u32, plus: bool) -> String {
+    let s = match *obj {
+        Object::Number(n) => BigInt::from_i64(n as i64).to_string_radix(radix),
+        Object::Bignum(ref n) => n.to_string_radix(radix),
+        _ => return obj.to_string()
+    };
+    if plus && !s.starts_with('-') { format!("+{}", s) } else { s }
+}
+
+fn run(ctrl: &[char], args: &mut Peekable<impl Iterator<Item = Rc<Object>>>, out: &mut String) -> Result<()> {
+    let mut i = 0;
+    while i < ctrl.len() {
+        if ctrl[i] != '~' {
+            out.push(ctrl[i]);
+            i += 1;
+            continue;
+        }
+        let (d, len) = parse(&ctrl[i..])?;
+        i += len;
+        match d.kind {
+            'a' => pad(out, &next_arg(args)?.to_string(), &d, d.at),
+            's' => pad(out, &format!("{:#}", next_arg(args)?), &d, d.at),
+            'd' | 'x' | 'b' | 'o' => {
+                let radix = match d.kind { 'd' => 10, 'x' => 16, 'b' => 2, _ => 8 };
+                let arg = next_arg(args)?;
+                pad(out, &integer(&arg, radix, d.at), &d, true);
+            }
+            '%' => out.push('\n'),
+            '~' => out.push('~'),
+            '{' => {
+                let end = i + find_close(&ctrl[i..])?;
+                let body = &ctrl[i..end];
+                let list = next_arg(args)?;
+                let mut elems = object::list_to_vec(&list)?.into_iter().peekable();
+                while elems.peek().is_some() {
+                    let left = elems.len();
+                    run(body, &mut elems, out)?;
+                    // A body that takes no arguments would never finish.
+                    if elems.len() == left {
+                        break;
+                    }
+                }
+                i = end + parse(&ctrl[end..])?.1;
+            }
+            '}' => return Err(error("Unexpected ~} in format")),
+            c => return Err(error(&format!("Unknown format directive ~{}", c)))
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn format_test() {
+    let num = |n| Rc::new(Object::Number(n));
+    let s = |s: &str| Rc::new(Object::Str(s.to_string()));
+    let list = |objs: &[Rc<Object>]| Rc::new(object::vec_to_list(objs));
+    let f = |ctrl: &str, args: &[Rc<Object>]| format(ctrl, args).map_err(|e| e.to_string());
+    assert_eq!(f("~a and ~s~%", &[s("x"), s("y")]), Ok("x and \"y\"\n".to_string()));
+    assert_eq!(f("~d ~x ~b ~o ~~", &[num(-42), num(255), num(5), num(8)]), Ok("-42 ff 101 10 ~".to_string()));
+    assert_eq!(f("[~5a|~5@a|~5,'0d|~@d]", &[s("ab"), s("cd"), num(42), num(7)]),
+               Ok("[ab   |   cd|00042|+7]".to_string()));
+    assert_eq!(f("~d", &[s("x")]), Ok("x".to_string()));
+    assert_eq!(f("~{<~a:~a>~}", &[list(&[s("a"), num(1), s("b"), num(2)])]), Ok("<a:1><b:2>".to_string()));
+    assert_eq!(f("~{[~{~a~}]~}", &[list(&[list(&[num(1), num(2)]), list(&[])])]), Ok("[12][]".to_string()));
+    assert_eq!(f("~a ~a", &[num(1)]), Err("Not enough arguments for format".to_string()));
+    assert_eq!(f("~q", &[]), Err("Unknown format directive ~q".to_string()));
+    assert_eq!(f("~{~a", &[list(&[])]), Err("Missing ~} in format".to_string()));
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use crate::object::Object;
+
+// The equality used to compare keys, chosen when a table is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Equality {
+    Eq,
+    Eqv,
+    Equal
+}
+
+#[derive(Debug, Clone)]
+struct Key {
+    obj: Rc<Object>,
+    test: Equality
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.test {
+            Equality::Eq => self.obj.hash_eq(state),
+            Equality::Eqv => self.obj.hash_eqv(state),
+            Equality::Equal => self.obj.hash_equal(state)
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        match self.test {
+            Equality::Eq => self.obj.is_eq(&other.obj),
+            Equality::Eqv => self.obj.is_eqv(&other.obj),
+            Equality::Equal => self.obj.is_equal(&other.obj)
+        }
+    }
+}
+
+impl Eq for Key {}
+
+#[derive(Debug, Clone)]
+pub struct HashTable {
+    test: Equality,
+    map: HashMap<Key, Rc<Object>>
+}
+
+impl HashTable {
+    pub fn new(test: Equality) -> Self {
+        HashTable { test, map: HashMap::new() }
+    }
+
+    fn key(&self, obj: &Rc<Object>) -> Key {
+        Key { obj: obj.clone(), test: self.test }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get(&self, key: &Rc<Object>) -> Option<Rc<Object>> {
+        self.map.get(&self.key(key)).cloned()
+    }
+
+    pub fn insert(&mut self, key: Rc<Object>, value: Rc<Object>) {
+        let key = self.key(&key);
+        self.map.insert(key, value);
+    }
+
+    pub fn remove(&mut self, key: &Rc<Object>) -> Option<Rc<Object>> {
+        self.map.remove(&self.key(key))
+    }
+
+    pub fn entries(&self) -> Vec<(Rc<Object>, Rc<Object>)> {
+        self.map.iter().map(|(k, v)| (k.obj.clone(), v.clone())).collect()
+    }
+}
+
+#[test]
+fn hash_table_test() {
+    let list = |a, b| Rc::new(crate::object::vec_to_list(&[
+        Rc::new(Object::Number(a)),
+        Rc::new(Object::Number(b))
+    ]));
+    let mut equal = HashTable::new(Equality::Equal);
+    equal.insert(list(1, 2), Rc::new(Object::T));
+    assert_eq!(equal.get(&list(1, 2)), Some(Rc::new(Object::T)));
+    assert_eq!(equal.get(&list(2, 1)), None);
+
+    let mut eq = HashTable::new(Equality::Eq);
+    let key = list(1, 2);
+    eq.insert(key.clone(), Rc::new(Object::T));
+    eq.insert(Rc::new(Object::Number(1)), Rc::new(Object::Nil));
+    assert_eq!(eq.get(&list(1, 2)), None);
+    assert_eq!(eq.get(&key), Some(Rc::new(Object::T)));
+    assert_eq!(eq.get(&Rc::new(Object::Number(1))), Some(Rc::new(Object::Nil)));
+    assert_eq!(eq.remove(&key), Some(Rc::new(Object::T)));
+    assert_eq!(eq.len(), 1);
+}
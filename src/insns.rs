@@ -1,5 +1,6 @@
 use crate::object::Object;
 use crate::env::Location;
+use crate::namespace::Global;
 
 use std::rc::Rc;
 
@@ -8,6 +9,9 @@ pub enum Insn {
     Inil,
     Ildc(Rc<Object>),
     Ild(Location),
+    Igld(Rc<Global>),
+    Idef(Rc<Global>),
+    Ipop,
     Iatom,
     Inull,
     Icar,
@@ -25,6 +29,7 @@ pub enum Insn {
     Isel(Code, Code),
     Ijoin,
     Ildf(Code),
+    Iargs(usize, bool),
     Iap,
     Irtn
 }
@@ -1,7 +1,21 @@
+pub mod bigint;
+pub mod builtins;
 pub mod compiler;
 pub mod env;
 pub mod error;
+pub mod format;
+pub mod hashtable;
 pub mod insns;
+pub mod module;
+pub mod namespace;
+pub mod number;
 pub mod object;
+pub mod pmap;
+pub mod port;
+pub mod prelude;
+pub mod pvector;
+pub mod ratio;
 pub mod reader;
+pub mod record;
+pub mod symbol;
 pub mod vm;
@@ -1,38 +1,125 @@
 extern crate rusp;
 
-use std::io::{self, BufRead, Write};
-use rusp::compiler as comp;
-use rusp::error::Error;
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
+use rusp::error::{Error, error};
 use rusp::object::Object;
+use rusp::prelude;
 use rusp::reader;
 use rusp::vm::Vm;
 
-fn prompt() -> io::Result<()> {
-    print!("> ");
+// Typed alone on a line, discards the input read so far.
+const RESET: &str = ",reset";
+
+const USAGE: &str = "usage: rusp [--no-prelude] [file [args...] | -e expr [args...]]";
+
+fn prompt(continued: bool) -> io::Result<()> {
+    print!("{}", if continued { ".. " } else { "> " });
     io::stdout().flush()?;
 
     Ok(())
 }
 
-fn step(expr: &Object) -> Result<(), Error> {
-    let code = comp::compile(&expr)?;
-    let mut vm = Vm::new(code);
-    let v = vm.run()?;
-    println!("{}", *v);
+// Evaluates every expression in `src` in order and returns the last value.
+fn eval_all(src: &str, vm: &mut Vm) -> Result<Rc<Object>, Error> {
+    let mut ret = Rc::new(Object::Nil);
+    for expr in reader::read(src.chars()) {
+        ret = vm.eval(&expr?)?;
+    }
+    Ok(ret)
+}
 
-    Ok(())
+// Evaluates every expression in `input` in order, stopping at the first
+// error. Returns false without evaluating anything if the input ends in
+// the middle of an expression.
+fn eval_input(input: &str, vm: &mut Vm) -> bool {
+    let exprs: Vec<_> = reader::read(input.chars()).collect();
+    let first_err = exprs.iter().find_map(|expr| expr.as_ref().err());
+    if first_err.is_some_and(Error::is_incomplete) {
+        return false;
+    }
+    for expr in exprs {
+        match expr.and_then(|expr| vm.eval(&expr)) {
+            Ok(v) => println!("{:#}", *v),
+            Err(err) => {
+                println!("Error: {}", err);
+                break;
+            }
+        }
+    }
+    true
 }
 
-fn main() -> io::Result<()> {
-    prompt()?;
+fn repl(vm: &mut Vm) -> io::Result<()> {
+    let mut input = String::new();
+    prompt(false)?;
     let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        if let Some(expr) = reader::read_string(&line?) {
-            if let Err(err) = step(&expr) {
-                println!("Error: {}", err);
+    let mut line = String::new();
+    // Stdin is locked only while a line is read, since the code evaluated
+    // may read from it too.
+    while stdin.read_line(&mut line)? > 0 {
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        if line.trim() == RESET {
+            input.clear();
+        } else {
+            input.push_str(&line);
+            input.push('\n');
+            if eval_input(&input, vm) {
+                input.clear();
             }
         }
-        prompt()?;
+        line.clear();
+        prompt(!input.is_empty())?;
+    }
+    if !input.trim().is_empty() {
+        println!("Error: unexpected end of input");
+    }
+    Ok(())
+}
+
+// Runs a script or a one-liner. The value of a one-liner is written.
+fn run(args: &[String], vm: &mut Vm) -> Result<(), Error> {
+    match args {
+        [flag, expr, rest @ ..] if flag == "-e" => {
+            vm.set_command_line([flag.clone()].iter().chain(rest).cloned().collect());
+            let v = eval_all(expr, vm)?;
+            println!("{:#}", *v);
+            Ok(())
+        }
+        [flag] if flag == "-e" => Err(error(USAGE)),
+        [path, ..] => {
+            vm.set_command_line(args.to_vec());
+            vm.load_file(path).map(|_| ())
+        }
+        [] => unreachable!("run is called with arguments")
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = env::args().collect();
+    let no_prelude = args.get(1).is_some_and(|arg| arg == "--no-prelude");
+    if no_prelude {
+        args.remove(1);
+    }
+    let mut vm = Vm::default();
+    vm.add_module_path(".");
+    if !no_prelude {
+        prelude::load(&mut vm).expect("prelude must load");
+    }
+    if args.len() < 2 {
+        vm.set_command_line(args);
+        return repl(&mut vm);
+    }
+    if let Err(err) = run(&args[1..], &mut vm) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
     }
     Ok(())
 }
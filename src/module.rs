@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::result;
+use crate::error::{Error, error};
+use crate::namespace::Namespace;
+use crate::object::{self, Native, Object};
+use crate::reader;
+use crate::symbol::{self, Symbol};
+use crate::vm::Vm;
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Module `name` is looked up as the file `name.lisp`.
+const EXTENSION: &str = "lisp";
+
+#[derive(Debug)]
+pub struct Module {
+    ns: Rc<Namespace>,
+    exports: Vec<Symbol>
+}
+
+#[derive(Debug, Default)]
+pub struct Modules {
+    search_path: Vec<PathBuf>,
+    defined: HashMap<Symbol, Rc<Module>>,
+    // Modules whose files are being loaded, in import order.
+    loading: Vec<Symbol>,
+    // The file being loaded, which `load` and `import` look next to.
+    current_file: Option<PathBuf>
+}
+
+impl Modules {
+    pub fn add_search_path(&mut self, dir: PathBuf) {
+        self.search_path.push(dir);
+    }
+
+    fn current_dir(&self) -> Option<&Path> {
+        self.current_file.as_ref().and_then(|file| file.parent())
+    }
+
+    fn find(&self, name: Symbol) -> Option<PathBuf> {
+        let file = format!("{}.{}", name, EXTENSION);
+        self.current_dir().into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+}
+
+// Evaluates every expression in the file in the current namespace, and
+// returns the last value.
+pub fn load(vm: &mut Vm, path: &Path) -> Result<Rc<Object>> {
+    let path = match vm.modules().current_dir() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf()
+    };
+    let src = fs::read_to_string(&path).map_err(|e| {
+        error(&format!("Can't read {}: {}", path.display(), e))
+    })?;
+    let prev = vm.modules().current_file.replace(path);
+    let ret = eval_all(vm, &src);
+    vm.modules().current_file = prev;
+    ret
+}
+
+fn eval_all(vm: &mut Vm, src: &str) -> Result<Rc<Object>> {
+    let mut ret = Rc::new(Object::Nil);
+    for expr in reader::read(src.chars()) {
+        ret = vm.eval_nested(&expr?)?;
+    }
+    Ok(ret)
+}
+
+// Runs `f` with `ns` as the current namespace.
+fn with_namespace<T>(vm: &mut Vm, ns: Rc<Namespace>, f: impl FnOnce(&mut Vm) -> T) -> T {
+    let prev = vm.set_namespace(ns);
+    let ret = f(vm);
+    vm.set_namespace(prev);
+    ret
+}
+
+fn symbol(obj: &Object, msg: &str) -> Result<Symbol> {
+    match *obj {
+        Object::Symbol(sym) => Ok(sym),
+        _ => Err(error(msg))
+    }
+}
+
+// A module sees the natives and the prelude, but neither top-level
+// definitions nor those of the module importing it.
+fn module_namespace(vm: &Vm) -> Rc<Namespace> {
+    Rc::new(Namespace::with_parent(vm.namespace().root()))
+}
+
+fn define(vm: &mut Vm, name: Symbol, exports: Vec<Symbol>, body: &[Rc<Object>]) -> Result<()> {
+    let ns = module_namespace(vm);
+    with_namespace(vm, ns.clone(), |vm| {
+        body.iter().try_for_each(|expr| vm.eval_nested(expr).map(|_| ()))
+    })?;
+    if let Some(sym) = exports.iter().find(|sym| !ns.defines(**sym)) {
+        return Err(error(&format!("Module {} does not define {}", name, sym)));
+    }
+    vm.modules().defined.insert(name, Rc::new(Module { ns, exports }));
+    Ok(())
+}
+
+fn import(vm: &mut Vm, name: Symbol) -> Result<()> {
+    let module = match vm.modules().defined.get(&name) {
+        Some(module) => module.clone(),
+        None => {
+            let modules = vm.modules();
+            if let Some(i) = modules.loading.iter().position(|sym| *sym == name) {
+                let cycle: Vec<_> = modules.loading[i..].iter().chain(Some(&name))
+                    .map(|sym| sym.to_string())
+                    .collect();
+                return Err(error(&format!("Import cycle: {}", cycle.join(" -> "))));
+            }
+            let path = modules.find(name).ok_or_else(|| {
+                error(&format!("Module not found: {}", name))
+            })?;
+            modules.loading.push(name);
+            let ret = with_namespace(vm, module_namespace(vm), |vm| load(vm, &path));
+            vm.modules().loading.pop();
+            ret?;
+            vm.modules().defined.get(&name).cloned().ok_or_else(|| {
+                error(&format!("{} does not define module {}", path.display(), name))
+            })?
+        }
+    };
+    for sym in &module.exports {
+        vm.namespace().import(*sym, module.ns.global(*sym));
+    }
+    Ok(())
+}
+
+// (module name (export sym ...) body ...) is compiled into a call to this
+// with the name, export list and body quoted, so that each body
+// expression is compiled only after the ones before it have run.
+pub const MODULE: Native = Native { name: "module", arity: (3, Some(3)), func: |vm, args| {
+    let name = symbol(&args[0], "module name must be symbol")?;
+    let exports = object::list_to_vec(&args[1])?;
+    let exports = match exports.split_first() {
+        Some((head, syms)) if **head == Object::Symbol(symbol::EXPORT) => syms,
+        _ => return Err(error("module must start with (export sym ...)"))
+    };
+    let exports = exports.iter()
+        .map(|sym| symbol(sym, "exported name must be symbol"))
+        .collect::<Result<Vec<_>>>()?;
+    define(vm, name, exports, &object::list_to_vec(&args[2])?)?;
+    Ok(Rc::new(Object::Symbol(name)))
+}};
+
+// (import name) is compiled into a call to this with the name quoted.
+pub const IMPORT: Native = Native { name: "import", arity: (1, Some(1)), func: |vm, args| {
+    let name = symbol(&args[0], "module name must be symbol")?;
+    import(vm, name)?;
+    Ok(Rc::new(Object::Symbol(name)))
+}};
+
+#[test]
+fn module_test() {
+    let dir = std::env::temp_dir().join(format!("rusp-module-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    let write = |name: &str, src: &str| fs::write(dir.join(name), src).unwrap();
+    write("lib/util.lisp", "(module util (export double) (define (twice x) (+ x x)) (define (double x) (twice x)))");
+    write("a.lisp", "(module a (export f) (import b) (define (f) 1))");
+    write("b.lisp", "(module b (export g) (import a) (define (g) 1))");
+    write("main.lisp", "(load \"defs.lisp\") (import util) (double x)");
+    write("defs.lisp", "(define x 21)");
+
+    let mut vm = Vm::default();
+    crate::prelude::load(&mut vm).expect("prelude must load");
+    vm.add_module_path(dir.join("lib"));
+    let mut eval = |src: &str| {
+        let expr = reader::read_string(src).unwrap().unwrap();
+        vm.eval(&expr).map(|v| v.to_string()).map_err(|e| e.to_string())
+    };
+    assert_eq!(eval(&format!("(load {:?})", dir.join("main.lisp"))), Ok("42".to_string()));
+    assert_eq!(eval("(double x)"), Ok("42".to_string()));
+    assert_eq!(eval("(twice 1)"), Err("unbound variable: twice".to_string()));
+    assert_eq!(eval(&format!("(load {:?})", dir.join("a.lisp"))),
+               Err("Import cycle: b -> a -> b".to_string()));
+    assert_eq!(eval("(module m (export y) (define x 1))"), Err("Module m does not define y".to_string()));
+    assert_eq!(eval("(module m (export y) (define y 1))"), Ok("m".to_string()));
+    assert_eq!(eval("(begin (import m) y)"), Ok("1".to_string()));
+    assert_eq!(eval("(import nothing)"), Err("Module not found: nothing".to_string()));
+    assert_eq!(eval("(define (pair x) (cons x x))"), Ok("pair".to_string()));
+    assert_eq!(eval("(module p (export f g) (define (f) (pair 1)) (define (g) (list 1 2)))"), Ok("p".to_string()));
+    assert_eq!(eval("(begin (import p) (g))"), Ok("(1 2)".to_string()));
+    assert_eq!(eval("(f)"), Err("unbound variable: pair".to_string()));
+    assert_eq!(eval("(module q (export pair) (define z 1))"), Err("Module q does not define pair".to_string()));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::builtins;
+use crate::object::Object;
+use crate::symbol::Symbol;
+
+// A global variable. Compiled code refers to the cell itself, so a
+// closure keeps seeing the namespace it was compiled in, and later
+// definitions are seen by code compiled before them.
+pub struct Global {
+    name: Symbol,
+    value: RefCell<Value>
+}
+
+enum Value {
+    Unbound,
+    Bound(Rc<Object>),
+    // Imported from another namespace.
+    Alias(Rc<Global>),
+    // Not defined here, so looked up in the parent namespace.
+    Inherited(Rc<Global>)
+}
+
+impl Global {
+    fn new(name: Symbol, value: Option<Rc<Object>>) -> Self {
+        let value = value.map_or(Value::Unbound, Value::Bound);
+        Global { name, value: RefCell::new(value) }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    pub fn get(&self) -> Option<Rc<Object>> {
+        match *self.value.borrow() {
+            Value::Unbound => None,
+            Value::Bound(ref obj) => Some(obj.clone()),
+            Value::Alias(ref global) | Value::Inherited(ref global) => global.get()
+        }
+    }
+
+    // Defining an imported variable shadows it instead of changing the
+    // original.
+    pub fn set(&self, value: Rc<Object>) {
+        *self.value.borrow_mut() = Value::Bound(value);
+    }
+}
+
+impl fmt::Debug for Global {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Global({})", self.name)
+    }
+}
+
+impl PartialEq for Global {
+    fn eq(&self, other: &Global) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Maps names to globals. Until it is defined otherwise, a name refers to
+// the global of the parent namespace if there is one, or else is bound to
+// the native of the same name.
+#[derive(Debug, Default)]
+pub struct Namespace {
+    globals: RefCell<HashMap<Symbol, Rc<Global>>>,
+    parent: Option<Rc<Namespace>>
+}
+
+impl Namespace {
+    pub fn new() -> Self {
+        Namespace { globals: RefCell::new(HashMap::new()), parent: None }
+    }
+
+    pub fn with_parent(parent: Rc<Namespace>) -> Self {
+        Namespace { globals: RefCell::new(HashMap::new()), parent: Some(parent) }
+    }
+
+    // The namespace at the top of the parent chain.
+    pub fn root(self: &Rc<Self>) -> Rc<Namespace> {
+        match self.parent {
+            Some(ref parent) => parent.root(),
+            None => self.clone()
+        }
+    }
+
+    pub fn global(&self, name: Symbol) -> Rc<Global> {
+        let mut globals = self.globals.borrow_mut();
+        globals.entry(name).or_insert_with(|| {
+            if let Some(ref parent) = self.parent {
+                let value = RefCell::new(Value::Inherited(parent.global(name)));
+                return Rc::new(Global { name, value });
+            }
+            let native = builtins::lookup(&name.name()).map(|native| Rc::new(Object::Native(native)));
+            Rc::new(Global::new(name, native))
+        }).clone()
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Rc<Object>> {
+        self.global(name).get()
+    }
+
+    // Whether `name` is defined or imported here rather than unbound or
+    // left to the parent.
+    pub fn defines(&self, name: Symbol) -> bool {
+        let global = self.global(name);
+        let value = global.value.borrow();
+        matches!(*value, Value::Bound(_) | Value::Alias(_))
+    }
+
+    pub fn define(&self, name: Symbol, value: Rc<Object>) {
+        self.global(name).set(value);
+    }
+
+    // Makes `name` refer to a global of another namespace, including in
+    // code already compiled.
+    pub fn import(&self, name: Symbol, global: Rc<Global>) {
+        let local = self.global(name);
+        if !Rc::ptr_eq(&local, &global) {
+            *local.value.borrow_mut() = Value::Alias(global);
+        }
+    }
+}
+
+#[test]
+fn namespace_test() {
+    let ns = Namespace::new();
+    let x = Symbol::intern("x");
+    assert_eq!(ns.get(x), None);
+    let global = ns.global(x);
+    ns.define(x, Rc::new(Object::Number(1)));
+    assert_eq!(global.get(), Some(Rc::new(Object::Number(1))));
+    assert!(matches!(ns.get(Symbol::intern("vector")).as_deref(), Some(Object::Native(_))));
+
+    let other = Namespace::new();
+    other.import(x, global.clone());
+    assert_eq!(other.get(x), Some(Rc::new(Object::Number(1))));
+    ns.define(x, Rc::new(Object::Number(2)));
+    assert_eq!(other.get(x), Some(Rc::new(Object::Number(2))));
+    other.define(x, Rc::new(Object::Number(3)));
+    assert_eq!(ns.get(x), Some(Rc::new(Object::Number(2))));
+
+    let ns = Rc::new(ns);
+    let child = Rc::new(Namespace::with_parent(ns.clone()));
+    let y = Symbol::intern("y");
+    assert_eq!(child.get(x), Some(Rc::new(Object::Number(2))));
+    assert!(!child.defines(x));
+    ns.define(y, Rc::new(Object::Number(4)));
+    assert_eq!(child.get(y), Some(Rc::new(Object::Number(4))));
+    child.define(x, Rc::new(Object::Number(5)));
+    assert!(child.defines(x));
+    assert_eq!(ns.get(x), Some(Rc::new(Object::Number(2))));
+    assert!(Rc::ptr_eq(&child.root(), &ns));
+}
@@ -0,0 +1,415 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::result;
+use crate::bigint::BigInt;
+use crate::error::{Error, error};
+use crate::object::Object;
+use crate::ratio::Ratio;
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Demotes a bignum back to a fixnum whenever it fits.
+pub fn from_bigint(n: BigInt) -> Object {
+    match n.to_i32() {
+        Some(n) => Object::Number(n),
+        None => Object::Bignum(n)
+    }
+}
+
+pub fn from_i64(n: i64) -> Object {
+    if n as i32 as i64 == n {
+        Object::Number(n as i32)
+    } else {
+        Object::Bignum(BigInt::from_i64(n))
+    }
+}
+
+fn not_number() -> Error {
+    error("Can't be converted to number")
+}
+
+fn division_by_zero() -> Error {
+    error("Division by zero")
+}
+
+// Demotes a rational to an integer if its denominator is 1.
+pub fn from_ratio(r: Ratio) -> Object {
+    if r.is_integer() {
+        from_bigint(r.numerator().clone())
+    } else {
+        Object::Ratio(r)
+    }
+}
+
+fn to_bigint(obj: &Object) -> Result<Cow<'_, BigInt>> {
+    match obj {
+        Object::Number(n) => Ok(Cow::Owned(BigInt::from_i64(*n as i64))),
+        Object::Bignum(n) => Ok(Cow::Borrowed(n)),
+        _ => Err(not_number())
+    }
+}
+
+fn to_ratio(obj: &Object) -> Result<Cow<'_, Ratio>> {
+    match obj {
+        Object::Ratio(r) => Ok(Cow::Borrowed(r)),
+        _ => Ok(Cow::Owned(Ratio::from_integer(to_bigint(obj)?.into_owned())))
+    }
+}
+
+pub fn to_f64(obj: &Object) -> Result<f64> {
+    match obj {
+        Object::Number(n) => Ok(*n as f64),
+        Object::Bignum(n) => Ok(n.to_f64()),
+        Object::Ratio(r) => Ok(r.to_f64()),
+        Object::Float(x) => Ok(*x),
+        _ => Err(not_number())
+    }
+}
+
+pub fn is_exact(obj: &Object) -> Result<bool> {
+    match obj {
+        Object::Number(_) | Object::Bignum(_) | Object::Ratio(_) => Ok(true),
+        Object::Float(_) => Ok(false),
+        _ => Err(not_number())
+    }
+}
+
+pub fn to_inexact(obj: &Object) -> Result<Object> {
+    Ok(Object::Float(to_f64(obj)?))
+}
+
+// Every finite float is a dyadic rational, so the conversion is exact.
+pub fn to_exact(obj: &Object) -> Result<Object> {
+    let x = match obj {
+        Object::Float(x) => *x,
+        _ if is_exact(obj)? => return Ok(obj.clone()),
+        _ => return Err(not_number())
+    };
+    if !x.is_finite() {
+        return Err(error("Can't be converted to exact number"));
+    }
+    let bits = x.to_bits();
+    let sign = if bits >> 63 == 0 { 1 } else { -1 };
+    let exp = ((bits >> 52) & 0x7ff) as i32;
+    let mantissa = if exp == 0 {
+        (bits & 0xf_ffff_ffff_ffff) << 1
+    } else {
+        (bits & 0xf_ffff_ffff_ffff) | 0x10_0000_0000_0000
+    };
+    let m = from_i64(sign * mantissa as i64);
+    let e = exp - 1075;
+    let scale = expt(&Object::Number(2), &Object::Number(e.abs()))?;
+    if e >= 0 {
+        mul(&m, &scale)
+    } else {
+        div(&m, &scale)
+    }
+}
+
+pub fn numerator(obj: &Object) -> Result<Object> {
+    match obj {
+        Object::Ratio(r) => Ok(from_bigint(r.numerator().clone())),
+        Object::Float(_) => to_inexact(&numerator(&to_exact(obj)?)?),
+        _ if is_exact(obj)? => Ok(obj.clone()),
+        _ => Err(not_number())
+    }
+}
+
+pub fn denominator(obj: &Object) -> Result<Object> {
+    match obj {
+        Object::Ratio(r) => Ok(from_bigint(r.denominator().clone())),
+        Object::Float(_) => to_inexact(&denominator(&to_exact(obj)?)?),
+        _ if is_exact(obj)? => Ok(Object::Number(1)),
+        _ => Err(not_number())
+    }
+}
+
+// Applies `float` if either operand is inexact, the rational operations
+// if either is a ratio, and the exact integer operations otherwise.
+fn arith_op(x: &Object, y: &Object,
+            fix: impl FnOnce(i64, i64) -> i64,
+            big: impl FnOnce(&BigInt, &BigInt) -> BigInt,
+            ratio: impl FnOnce(&Ratio, &Ratio) -> Ratio,
+            float: impl FnOnce(f64, f64) -> f64) -> Result<Object> {
+    match (x, y) {
+        (Object::Number(x), Object::Number(y)) => Ok(from_i64(fix(*x as i64, *y as i64))),
+        (Object::Float(_), _) | (_, Object::Float(_)) => {
+            Ok(Object::Float(float(to_f64(x)?, to_f64(y)?)))
+        }
+        (Object::Ratio(_), _) | (_, Object::Ratio(_)) => {
+            Ok(from_ratio(ratio(&*to_ratio(x)?, &*to_ratio(y)?)))
+        }
+        _ => Ok(from_bigint(big(&*to_bigint(x)?, &*to_bigint(y)?)))
+    }
+}
+
+pub fn add(x: &Object, y: &Object) -> Result<Object> {
+    arith_op(x, y, |x, y| x + y, |x, y| x + y, |x, y| x + y, |x, y| x + y)
+}
+
+pub fn sub(x: &Object, y: &Object) -> Result<Object> {
+    arith_op(x, y, |x, y| x - y, |x, y| x - y, |x, y| x - y, |x, y| x - y)
+}
+
+pub fn mul(x: &Object, y: &Object) -> Result<Object> {
+    arith_op(x, y, |x, y| x * y, |x, y| x * y, |x, y| x * y, |x, y| x * y)
+}
+
+// Exact division yields a ratio unless the quotient is an integer.
+pub fn div(x: &Object, y: &Object) -> Result<Object> {
+    if let (Object::Float(_), _) | (_, Object::Float(_)) = (x, y) {
+        return Ok(Object::Float(to_f64(x)? / to_f64(y)?));
+    }
+    let (x, y) = (to_ratio(x)?, to_ratio(y)?);
+    if y.is_zero() {
+        return Err(division_by_zero());
+    }
+    Ok(from_ratio(&*x / &*y))
+}
+
+// Returns None if either operand is NaN.
+pub fn compare(x: &Object, y: &Object) -> Result<Option<Ordering>> {
+    match (x, y) {
+        (Object::Number(x), Object::Number(y)) => Ok(Some(x.cmp(y))),
+        (Object::Float(x), Object::Float(y)) => Ok(x.partial_cmp(y)),
+        (Object::Float(x), _) => compare_float(*x, y),
+        (_, Object::Float(y)) => Ok(compare_float(*y, x)?.map(Ordering::reverse)),
+        (Object::Ratio(_), _) | (_, Object::Ratio(_)) => {
+            Ok(Some(to_ratio(x)?.cmp(&to_ratio(y)?)))
+        }
+        _ => Ok(Some(to_bigint(x)?.cmp(&to_bigint(y)?)))
+    }
+}
+
+// Compares a float with an exact number as the exact value the float
+// stands for, since the exact number may not be representable as a float.
+fn compare_float(x: f64, y: &Object) -> Result<Option<Ordering>> {
+    if x.is_nan() {
+        is_exact(y)?;
+        Ok(None)
+    } else if x.is_infinite() {
+        is_exact(y)?;
+        Ok(Some(if x > 0.0 { Ordering::Greater } else { Ordering::Less }))
+    } else {
+        compare(&to_exact(&Object::Float(x))?, y)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Floor,
+    Ceiling,
+    Round,
+    Truncate
+}
+
+// Exact integers are left untouched, ratios are rounded to an exact
+// integer and floats to an integral float. `Round` rounds half to even.
+pub fn round(obj: &Object, mode: Rounding) -> Result<Object> {
+    match obj {
+        Object::Float(x) => {
+            let f = match mode {
+                Rounding::Floor => f64::floor,
+                Rounding::Ceiling => f64::ceil,
+                Rounding::Round => f64::round_ties_even,
+                Rounding::Truncate => f64::trunc
+            };
+            Ok(Object::Float(f(*x)))
+        }
+        Object::Ratio(r) => {
+            let (q, rem) = r.numerator().div_rem(r.denominator()).unwrap();
+            let one = BigInt::from_i64(if rem.is_negative() { -1 } else { 1 });
+            let away = &q + &one;
+            let q = match mode {
+                Rounding::Floor if rem.is_negative() => away,
+                Rounding::Ceiling if !rem.is_negative() => away,
+                Rounding::Round => {
+                    let twice = &rem.abs() * &BigInt::from_i64(2);
+                    match twice.cmp(r.denominator()) {
+                        Ordering::Greater => away,
+                        Ordering::Equal if !q.is_even() => away,
+                        _ => q
+                    }
+                }
+                _ => q
+            };
+            Ok(from_bigint(q))
+        }
+        _ if is_exact(obj)? => Ok(obj.clone()),
+        _ => Err(not_number())
+    }
+}
+
+fn exact_sqrt(obj: &Object) -> Result<Option<Object>> {
+    if let Object::Ratio(r) = obj {
+        let num = exact_sqrt(&from_bigint(r.numerator().clone()))?;
+        let den = exact_sqrt(&from_bigint(r.denominator().clone()))?;
+        return match (num, den) {
+            (Some(num), Some(den)) => Ok(Some(div(&num, &den)?)),
+            _ => Ok(None)
+        };
+    }
+    let n = to_bigint(obj)?;
+    if n.is_negative() {
+        return Ok(None);
+    }
+    let r = n.isqrt();
+    Ok(if &r * &r == *n { Some(from_bigint(r)) } else { None })
+}
+
+// Exact for squares of exact numbers, inexact otherwise.
+pub fn sqrt(obj: &Object) -> Result<Object> {
+    if is_exact(obj)? {
+        if let Some(r) = exact_sqrt(obj)? {
+            return Ok(r);
+        }
+    }
+    Ok(Object::Float(to_f64(obj)?.sqrt()))
+}
+
+pub fn expt(x: &Object, y: &Object) -> Result<Object> {
+    match y {
+        Object::Number(n) if is_exact(x)? => {
+            let (mut base, mut k) = (x.clone(), n.unsigned_abs());
+            let mut ret = Object::Number(1);
+            while k > 0 {
+                if k & 1 == 1 {
+                    ret = mul(&ret, &base)?;
+                }
+                k >>= 1;
+                if k > 0 {
+                    base = mul(&base, &base)?;
+                }
+            }
+            if *n < 0 {
+                div(&Object::Number(1), &ret)
+            } else {
+                Ok(ret)
+            }
+        }
+        _ => Ok(Object::Float(to_f64(x)?.powf(to_f64(y)?)))
+    }
+}
+
+pub fn parse(s: &str) -> Option<Object> {
+    BigInt::parse(s).map(from_bigint)
+        .or_else(|| parse_ratio(s))
+        .or_else(|| parse_float(s).map(Object::Float))
+}
+
+fn parse_ratio(s: &str) -> Option<Object> {
+    let (num, den) = s.split_once('/')?;
+    if !den.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (num, den) = (BigInt::parse(num)?, BigInt::parse(den)?);
+    if den.is_zero() {
+        return None;
+    }
+    Some(from_ratio(Ratio::new(num, den)))
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    match s {
+        "+inf.0" => return Some(f64::INFINITY),
+        "-inf.0" => return Some(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => return Some(f64::NAN),
+        _ => {}
+    }
+    // str::parse also accepts words like "inf" and "NaN", which are
+    // symbols for us, so check the syntax up front.
+    let body = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let (mantissa, exponent) = match body.find(['e', 'E']) {
+        Some(i) => (&body[..i], Some(&body[i + 1..])),
+        None => (body, None)
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if int.len() + frac.len() == 0 || !digits(int) || !digits(frac) {
+        return None;
+    }
+    if let Some(e) = exponent {
+        let e = e.strip_prefix(['+', '-']).unwrap_or(e);
+        if e.is_empty() || !digits(e) {
+            return None;
+        }
+    }
+    s.parse().ok()
+}
+
+// Prints the shortest representation that reads back as the same float.
+pub fn write_float(f: &mut fmt::Formatter, x: f64) -> fmt::Result {
+    if x.is_nan() {
+        write!(f, "+nan.0")
+    } else if x.is_infinite() {
+        write!(f, "{}inf.0", if x > 0.0 { "+" } else { "-" })
+    } else {
+        write!(f, "{:?}", x)
+    }
+}
+
+#[test]
+fn number_test() {
+    let num = |s| parse(s).expect("valid number");
+    assert_eq!(mul(&Object::Number(100000), &Object::Number(100000)).unwrap(),
+               num("10000000000"));
+    assert_eq!(add(&Object::Number(i32::MAX), &Object::Number(1)).unwrap(),
+               Object::Bignum(BigInt::from_i64(1 << 31)));
+    assert_eq!(sub(&num("2147483648"), &Object::Number(1)).unwrap(),
+               Object::Number(i32::MAX));
+    assert_eq!(div(&Object::Number(i32::MIN), &Object::Number(-1)).unwrap(),
+               num("2147483648"));
+    assert_eq!(div(&num("10000000000"), &Object::Number(100000)).unwrap(),
+               Object::Number(100000));
+    assert!(div(&num("10000000000"), &Object::Number(0)).is_err());
+    assert_eq!(compare(&num("-10000000000"), &Object::Number(-1)).unwrap(),
+               Some(Ordering::Less));
+
+    assert_eq!(add(&num("1.5"), &Object::Number(1)).unwrap(), Object::Float(2.5));
+    assert_eq!(div(&Object::Number(1), &num("0.0")).unwrap(), Object::Float(f64::INFINITY));
+    assert_eq!(compare(&num("+nan.0"), &num("+nan.0")).unwrap(), None);
+    assert_eq!(num("-2e10"), Object::Float(-2e10));
+    assert_eq!(parse("inf"), None);
+    assert_eq!(parse("1.2.3"), None);
+    assert_eq!(parse("e5"), None);
+    assert_eq!(sqrt(&num("10000000000")).unwrap(), Object::Number(100000));
+    assert_eq!(sqrt(&Object::Number(2)).unwrap(), Object::Float(2f64.sqrt()));
+    assert_eq!(sqrt(&expt(&Object::Number(10), &Object::Number(40)).unwrap()).unwrap(),
+               num("100000000000000000000"));
+    assert_eq!(sqrt(&num("99999999999999999999")).unwrap(), Object::Float(1e10));
+    // 2^53 + 1 rounds to the float 2^53, but they are not equal.
+    assert_eq!(compare(&num("9007199254740993"), &num("9007199254740992.0")).unwrap(),
+               Some(Ordering::Greater));
+    assert_eq!(compare(&num("-inf.0"), &num("-100000000000000000000")).unwrap(), Some(Ordering::Less));
+    assert_eq!(compare(&num("1/3"), &num("+nan.0")).unwrap(), None);
+    assert_eq!(expt(&Object::Number(2), &Object::Number(100)).unwrap(),
+               num("1267650600228229401496703205376"));
+    assert_eq!(expt(&Object::Number(2), &num("0.5")).unwrap(), Object::Float(2f64.sqrt()));
+    assert_eq!(round(&num("2.5"), Rounding::Round).unwrap(), Object::Float(2.0));
+
+    assert_eq!(div(&Object::Number(7), &Object::Number(2)).unwrap(), num("7/2"));
+    assert_eq!(num("6/4").to_string(), "3/2");
+    assert_eq!(num("-4/2"), Object::Number(-2));
+    assert_eq!(parse("1/0"), None);
+    assert_eq!(parse("1/-2"), None);
+    assert_eq!(add(&num("1/3"), &num("2/3")).unwrap(), Object::Number(1));
+    assert_eq!(mul(&num("1/3"), &num("10000000000")).unwrap().to_string(), "10000000000/3");
+    assert_eq!(add(&num("1/2"), &num("0.25")).unwrap(), Object::Float(0.75));
+    assert!(div(&num("1/2"), &Object::Number(0)).is_err());
+    assert_eq!(compare(&num("1/3"), &num("0.3")).unwrap(), Some(Ordering::Greater));
+    assert_eq!(compare(&num("7/2"), &Object::Number(4)).unwrap(), Some(Ordering::Less));
+    assert_eq!(round(&num("5/2"), Rounding::Round).unwrap(), Object::Number(2));
+    assert_eq!(round(&num("-7/2"), Rounding::Round).unwrap(), Object::Number(-4));
+    assert_eq!(round(&num("-7/2"), Rounding::Floor).unwrap(), Object::Number(-4));
+    assert_eq!(round(&num("-7/2"), Rounding::Ceiling).unwrap(), Object::Number(-3));
+    assert_eq!(round(&num("-7/2"), Rounding::Truncate).unwrap(), Object::Number(-3));
+    assert_eq!(sqrt(&num("9/4")).unwrap(), num("3/2"));
+    assert_eq!(expt(&Object::Number(2), &Object::Number(-2)).unwrap(), num("1/4"));
+    assert_eq!(to_exact(&num("0.75")).unwrap(), num("3/4"));
+    assert_eq!(to_exact(&num("-1e20")).unwrap(), num("-100000000000000000000"));
+    assert_eq!(denominator(&num("0.1")).unwrap(), Object::Float(36028797018963968.0));
+    for s in ["0.1", "-2e-7", "1e21", "123.456", "+inf.0", "-inf.0", "+nan.0"] {
+        assert_eq!(num(s).to_string(), s);
+    }
+}
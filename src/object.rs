@@ -1,65 +1,265 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::Rc;
 use std::result;
+use crate::bigint::BigInt;
 use crate::env::Env;
 use crate::error::{Error, error};
+use crate::hashtable::HashTable;
 use crate::insns::Code;
+use crate::number;
+use crate::pmap::PersistentMap;
+use crate::pvector::PersistentVector;
+use crate::ratio::Ratio;
+use crate::port::Port;
+use crate::record::RecordType;
+use crate::symbol::Symbol;
+use crate::vm::Vm;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Object {
     Nil,
     T,
+    // Returned by the readers at the end of their input.
+    Eof,
     Number(i32),
-    Symbol(String),
+    Bignum(BigInt),
+    Ratio(Ratio),
+    Float(f64),
+    Symbol(Symbol),
+    Keyword(Symbol),
+    Char(char),
+    Str(String),
     Cons(Rc<Object>, Rc<Object>),
-    Func(Code, Rc<Env>)
+    Vector(RefCell<Vec<Rc<Object>>>),
+    HashTable(RefCell<HashTable>),
+    Map(PersistentMap),
+    PVector(PersistentVector),
+    Record(Rc<RecordType>, RefCell<Vec<Rc<Object>>>),
+    RecordType(Rc<RecordType>),
+    Port(Rc<Port>),
+    Func(Code, Rc<Env>),
+    Native(Native)
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
-fn write_list(f: &mut fmt::Formatter, obj: &Object) -> fmt::Result {
-    match *obj {
-        Object::Cons(ref car, ref cdr) => {
-            write!(f, "{}", car)?;
-            match **cdr {
-                Object::Nil => Ok(()),
-                _ => {
-                    write!(f, " ")?;
-                    write_list(f, cdr)
-                }
-            }
+pub type NativeFn = fn(&mut Vm, &[Rc<Object>]) -> Result<Rc<Object>>;
+
+// A function implemented in Rust. `arity` is the minimum number of
+// arguments and the maximum, if any.
+#[derive(Clone)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: (usize, Option<usize>),
+    pub func: NativeFn
+}
+
+impl Native {
+    pub fn check_arity(&self, nargs: usize) -> Result<()> {
+        let (min, max) = self.arity;
+        if nargs < min {
+            let msg = format!("too less arguments to {}", self.name);
+            return Err(error(&msg));
+        }
+        if max.is_some_and(|max| nargs > max) {
+            let msg = format!("too many arguments to {}", self.name);
+            return Err(error(&msg));
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter, mut obj: &Object) -> fmt::Result {
+    while let Object::Cons(ref car, ref cdr) = *obj {
+        fmt::Display::fmt(car, f)?;
+        match **cdr {
+            Object::Nil => return Ok(()),
+            Object::Cons(..) => write!(f, " ")?,
+            _ => write!(f, " . ")?
         }
-        _ => write!(f, ". {}", obj)
+        obj = cdr;
     }
+    fmt::Display::fmt(obj, f)
 }
 
+fn write_seq(f: &mut fmt::Formatter, open: &str, objs: &[Rc<Object>], close: &str) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, obj) in objs.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        fmt::Display::fmt(obj, f)?;
+    }
+    write!(f, "{}", close)
+}
+
+// Names accepted by the reader after `#\\`, and used when printing.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("escape", '\x1b'),
+    ("delete", '\x7f')
+];
+
+// Writes `s` between `quote`s, escaped so that the reader gives it back.
+fn write_escaped(f: &mut fmt::Formatter, s: &str, quote: char) -> fmt::Result {
+    write!(f, "{}", quote)?;
+    for c in s.chars() {
+        match c {
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            _ if c == quote => write!(f, "\\{}", c)?,
+            _ if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            _ => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "{}", quote)
+}
+
+// Whether a symbol has to be written as `|name|` to be read back, because
+// the name would read as something else or would not read as one token.
+fn needs_bars(name: &str) -> bool {
+    name.is_empty() || name == "t" || name == "nil" || name == "." ||
+        name.starts_with('#') || (name.starts_with(':') && name.len() > 1) ||
+        number::parse(name).is_some() ||
+        name.chars().any(|c| c.is_whitespace() || c.is_control() || "()[]{}'\",;|\\".contains(c))
+}
+
+fn write_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => write!(f, "#\\{}", name),
+        None if c.is_control() => write!(f, "#\\x{:x}", c as u32),
+        None => write!(f, "#\\{}", c)
+    }
+}
+
+// `{}` prints objects for `display`, and `{:#}` for `write`, which prints
+// strings, symbols, keywords and chars in reader syntax. Written data
+// reads back as an equal object, except for what has no reader syntax,
+// like functions.
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Object::Nil => write!(f, "nil"),
             Object::T => write!(f, "t"),
+            Object::Eof => write!(f, "#<eof>"),
             Object::Number(ref num) => write!(f,"{}", num),
+            Object::Bignum(ref num) => write!(f, "{}", num),
+            Object::Ratio(ref num) => write!(f, "{}", num),
+            Object::Float(num) => number::write_float(f, num),
+            Object::Symbol(ref sym) if f.alternate() && needs_bars(&sym.name()) => {
+                write_escaped(f, &sym.name(), '|')
+            }
             Object::Symbol(ref sym) => write!(f, "{}", sym),
+            Object::Keyword(ref sym) if f.alternate() && needs_bars(&sym.name()) => {
+                write!(f, ":")?;
+                write_escaped(f, &sym.name(), '|')
+            }
+            Object::Keyword(ref sym) => write!(f, ":{}", sym),
+            Object::Char(c) if f.alternate() => write_char(f, c),
+            Object::Char(c) => write!(f, "{}", c),
+            Object::Str(ref s) if f.alternate() => write_escaped(f, s, '"'),
+            Object::Str(ref s) => write!(f, "{}", s),
             Object::Cons(..) => {
                 write!(f, "(")?;
                 write_list(f, self)?;
                 write!(f, ")")
             }
-            Object::Func(..) => write!(f, "#<func>")
+            Object::Vector(ref v) => write_seq(f, "#(", &v.borrow(), ")"),
+            Object::PVector(ref v) => write_seq(f, "#[", &v.to_vec(), "]"),
+            Object::Map(ref m) => {
+                let kvs: Vec<_> = m.entries().into_iter().flat_map(|(k, v)| [k, v]).collect();
+                write_seq(f, "{", &kvs, "}")
+            }
+            Object::HashTable(ref table) => {
+                write!(f, "#<hash-table {}>", table.borrow().len())
+            }
+            Object::Record(ref rtd, ref fields) => {
+                write!(f, "#<{}", rtd.name())?;
+                for (name, obj) in rtd.fields().iter().zip(fields.borrow().iter()) {
+                    write!(f, " {}: ", name)?;
+                    fmt::Display::fmt(obj, f)?;
+                }
+                write!(f, ">")
+            }
+            Object::RecordType(ref rtd) => write!(f, "#<record-type {}>", rtd.name()),
+            Object::Port(ref port) if port.is_input() => write!(f, "#<input-port>"),
+            Object::Port(_) => write!(f, "#<output-port>"),
+            Object::Func(..) => write!(f, "#<func>"),
+            Object::Native(ref native) => write!(f, "#<native {}>", native.name)
         }
     }
 }
 
 impl Object {
     pub fn is_atom(&self) -> bool {
-        match self {
-            Object::Cons(..) => false,
-            _ => true
-        }
+        !matches!(self, Object::Cons(..))
     }
 
     pub fn is_null(&self) -> bool {
-        *self == Object::Nil
+        matches!(self, Object::Nil)
+    }
+
+    // Identity. Objects that have no identity of their own (nil, t, eof,
+    // fixnums, symbols, keywords, characters and natives) are compared by
+    // value, and ports by the port they refer to.
+    pub fn is_eq(&self, other: &Object) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+        match (self, other) {
+            (Object::Nil, Object::Nil) | (Object::T, Object::T) | (Object::Eof, Object::Eof) => true,
+            (Object::Number(x), Object::Number(y)) => x == y,
+            (Object::Symbol(x), Object::Symbol(y)) => x == y,
+            (Object::Keyword(x), Object::Keyword(y)) => x == y,
+            (Object::Char(x), Object::Char(y)) => x == y,
+            (Object::Native(x), Object::Native(y)) => x == y,
+            (Object::Port(x), Object::Port(y)) => Rc::ptr_eq(x, y),
+            _ => false
+        }
+    }
+
+    // Like `is_eq`, but also compares numbers of the same exactness by
+    // value. Floats are compared bitwise, so that a NaN is eqv to itself
+    // and 0.0 is not eqv to -0.0.
+    pub fn is_eqv(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Bignum(x), Object::Bignum(y)) => x == y,
+            (Object::Ratio(x), Object::Ratio(y)) => x == y,
+            (Object::Float(x), Object::Float(y)) => x.to_bits() == y.to_bits(),
+            _ => self.is_eq(other)
+        }
+    }
+
+    // Structural equality. Safe on cyclic structures: after comparing a
+    // fixed number of pairs, each pair of objects being compared is
+    // recorded and assumed equal when met again.
+    pub fn is_equal(&self, other: &Object) -> bool {
+        let mut state = EqualState { budget: 1000, seen: HashSet::new() };
+        state.equal(self, other)
     }
 
     pub fn to_bool(&self) -> bool {
@@ -73,23 +273,265 @@ impl Object {
         }
     }
 
+    // Hashes consistent with `is_eq`: objects without identity are hashed
+    // by value and everything else by address.
+    pub fn hash_eq<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Object::Nil => 0.hash(state),
+            Object::T => 1.hash(state),
+            Object::Eof => 2.hash(state),
+            Object::Number(n) => n.hash(state),
+            Object::Symbol(sym) => sym.hash(state),
+            Object::Keyword(sym) => (':', sym).hash(state),
+            Object::Char(c) => c.hash(state),
+            Object::Native(native) => native.name.hash(state),
+            Object::Port(port) => Rc::as_ptr(port).hash(state),
+            _ => (self as *const Object).hash(state)
+        }
+    }
+
+    pub fn hash_eqv<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Object::Bignum(n) => n.hash(state),
+            Object::Ratio(r) => r.hash(state),
+            Object::Float(x) => x.to_bits().hash(state),
+            _ => self.hash_eq(state)
+        }
+    }
+
+    // Hashes consistent with `is_equal`. Only a bounded number of
+    // elements are looked at, which keeps hashing of long or cyclic
+    // structures finite.
+    pub fn hash_equal<H: Hasher>(&self, state: &mut H) {
+        self.hash_equal_bounded(state, &mut 64);
+    }
+
+    fn hash_equal_bounded<H: Hasher>(&self, state: &mut H, budget: &mut usize) {
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
+        match self {
+            Object::Str(s) => s.hash(state),
+            Object::Cons(car, cdr) => {
+                2.hash(state);
+                car.hash_equal_bounded(state, budget);
+                cdr.hash_equal_bounded(state, budget);
+            }
+            Object::Vector(v) => {
+                let v = v.borrow();
+                v.len().hash(state);
+                for obj in v.iter() {
+                    obj.hash_equal_bounded(state, budget);
+                }
+            }
+            Object::PVector(v) => {
+                v.len().hash(state);
+                for obj in v.to_vec() {
+                    obj.hash_equal_bounded(state, budget);
+                }
+            }
+            // Entries come in no particular order, so only the size is
+            // hashed.
+            Object::Map(m) => m.len().hash(state),
+            _ => self.hash_eqv(state)
+        }
+    }
+
+    pub fn to_char(&self) -> Result<char> {
+        match *self {
+            Object::Char(c) => Ok(c),
+            _ => Err(error("Can't be converted to char"))
+        }
+    }
+
+    pub fn to_str(&self) -> Result<&str> {
+        match self {
+            Object::Str(s) => Ok(s),
+            _ => Err(error("Can't be converted to string"))
+        }
+    }
+
+    pub fn to_vector(&self) -> Result<&RefCell<Vec<Rc<Object>>>> {
+        match self {
+            Object::Vector(v) => Ok(v),
+            _ => Err(error("Can't be converted to vector"))
+        }
+    }
+
+    pub fn to_port(&self) -> Result<&Rc<Port>> {
+        match self {
+            Object::Port(port) => Ok(port),
+            _ => Err(error("Can't be converted to port"))
+        }
+    }
+
+    pub fn to_hash_table(&self) -> Result<&RefCell<HashTable>> {
+        match self {
+            Object::HashTable(table) => Ok(table),
+            _ => Err(error("Can't be converted to hash table"))
+        }
+    }
+
+    pub fn to_map(&self) -> Result<&PersistentMap> {
+        match self {
+            Object::Map(m) => Ok(m),
+            _ => Err(error("Can't be converted to map"))
+        }
+    }
+
+    pub fn to_pvector(&self) -> Result<&PersistentVector> {
+        match self {
+            Object::PVector(v) => Ok(v),
+            _ => Err(error("Can't be converted to pvector"))
+        }
+    }
+
     pub fn car(&self) -> Result<Rc<Object>> {
         match self {
-            &Object::Nil => Ok(Rc::new(Object::Nil)),
-            &Object::Cons(ref car, _) => Ok(car.clone()),
+            Object::Nil => Ok(Rc::new(Object::Nil)),
+            Object::Cons(car, _) => Ok(car.clone()),
             _ => Err(error("Can't be converted to cons"))
         }
     }
 
     pub fn cdr(&self) -> Result<Rc<Object>> {
         match self {
-            &Object::Nil => Ok(Rc::new(Object::Nil)),
-            &Object::Cons(_, ref cdr) => Ok(cdr.clone()),
+            Object::Nil => Ok(Rc::new(Object::Nil)),
+            Object::Cons(_, cdr) => Ok(cdr.clone()),
             _ => Err(error("Can't be converted to cons"))
         }
     }
 }
 
+struct EqualState {
+    budget: usize,
+    seen: HashSet<(*const Object, *const Object)>
+}
+
+impl EqualState {
+    // Returns true if the pair is already being compared.
+    fn visit(&mut self, x: &Object, y: &Object) -> bool {
+        if self.budget > 0 {
+            self.budget -= 1;
+            return false;
+        }
+        !self.seen.insert((x, y))
+    }
+
+    // Compares `x` and `y` themselves, and pushes the pairs of their
+    // elements that are left to compare.
+    fn step(&mut self, x: &Object, y: &Object, pending: &mut Vec<(Rc<Object>, Rc<Object>)>) -> bool {
+        if x.is_eqv(y) {
+            return true;
+        }
+        match (x, y) {
+            (Object::Cons(car1, cdr1), Object::Cons(car2, cdr2)) => {
+                if !self.visit(x, y) {
+                    pending.push((cdr1.clone(), cdr2.clone()));
+                    pending.push((car1.clone(), car2.clone()));
+                }
+                true
+            }
+            (Object::Vector(v1), Object::Vector(v2)) => {
+                if !self.visit(x, y) {
+                    let (v1, v2) = (v1.borrow(), v2.borrow());
+                    if v1.len() != v2.len() {
+                        return false;
+                    }
+                    pending.extend(v1.iter().cloned().zip(v2.iter().cloned()).rev());
+                }
+                true
+            }
+            (Object::PVector(v1), Object::PVector(v2)) => {
+                if !self.visit(x, y) {
+                    if v1.len() != v2.len() {
+                        return false;
+                    }
+                    pending.extend(v1.to_vec().into_iter().zip(v2.to_vec()).rev());
+                }
+                true
+            }
+            (Object::Map(m1), Object::Map(m2)) => {
+                if !self.visit(x, y) {
+                    if m1.len() != m2.len() {
+                        return false;
+                    }
+                    for (k, v) in m1.entries() {
+                        match m2.get(&k) {
+                            Some(v2) => pending.push((v, v2)),
+                            None => return false
+                        }
+                    }
+                }
+                true
+            }
+            (Object::Str(s1), Object::Str(s2)) => s1 == s2,
+            _ => false
+        }
+    }
+
+    // The elements still to compare are kept on a stack rather than
+    // recursed into, so that neither long nor deeply nested structures
+    // can overflow the Rust stack.
+    fn equal(&mut self, x: &Object, y: &Object) -> bool {
+        let mut pending = vec![];
+        if !self.step(x, y, &mut pending) {
+            return false;
+        }
+        while let Some((x, y)) = pending.pop() {
+            if !self.step(&x, &y, &mut pending) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+thread_local! {
+    static NIL: Rc<Object> = Rc::new(Object::Nil);
+}
+
+// Detaches the pairs in a pair: the car is pushed onto `pending`, and
+// the cdr is returned.
+fn detach(obj: &mut Object, pending: &mut Vec<Rc<Object>>) -> Option<Rc<Object>> {
+    let nil = || NIL.try_with(Rc::clone).unwrap_or_else(|_| Rc::new(Object::Nil));
+    match *obj {
+        Object::Cons(ref mut car, ref mut cdr) => {
+            if matches!(**car, Object::Cons(..)) {
+                pending.push(mem::replace(car, nil()));
+            }
+            match **cdr {
+                Object::Cons(..) => Some(mem::replace(cdr, nil())),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+// Dropping a list would otherwise recurse once per pair, along both the
+// cdr and the car. Instead, each pair that is not shared is detached from
+// the pairs it holds before it is dropped.
+impl Drop for Object {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        let mut next = detach(self, &mut pending);
+        while let Some(pair) = next.take().or_else(|| pending.pop()) {
+            if let Ok(mut pair) = Rc::try_unwrap(pair) {
+                next = detach(&mut pair, &mut pending);
+            }
+        }
+    }
+}
+
+// Objects are compared with `is_equal`.
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        self.is_equal(other)
+    }
+}
+
 pub fn from_bool(b: bool) -> Object {
     if b { Object::T } else { Object::Nil }
 }
@@ -99,19 +541,23 @@ pub fn number(num: i32) -> Object {
 }
 
 pub fn symbol(name: &str) -> Object {
-    Object::Symbol(name.to_string())
+    Object::Symbol(Symbol::intern(name))
 }
 
 pub fn cons(car: Rc<Object>, cdr: Rc<Object>) -> Object {
     Object::Cons(car, cdr)
 }
 
+pub fn vector(objs: Vec<Rc<Object>>) -> Object {
+    Object::Vector(RefCell::new(objs))
+}
+
 pub fn list_to_vec(mut obj: &Object) -> Result<Vec<Rc<Object>>> {
     let mut ret = Vec::new();
     loop {
         match obj {
-            &Object::Nil => break,
-            &Object::Cons(ref car, ref cdr) => {
+            Object::Nil => break,
+            Object::Cons(car, cdr) => {
                 ret.push(car.clone());
                 obj = cdr.as_ref();
             }
@@ -121,10 +567,14 @@ pub fn list_to_vec(mut obj: &Object) -> Result<Vec<Rc<Object>>> {
     Ok(ret)
 }
 
+pub fn vec_to_list(objs: &[Rc<Object>]) -> Object {
+    objs.iter().rev().fold(Object::Nil, |acc, obj| cons(obj.clone(), Rc::new(acc)))
+}
+
 #[test]
 fn list_to_vec_test() {
-    assert_eq!(list_to_vec(Rc::new(Object::Nil)).expect("must not happen"), vec![]);
-    assert_eq!(list_to_vec(Rc::new(Object::Cons(
+    assert_eq!(list_to_vec(&Object::Nil).expect("must not happen"), vec![]);
+    assert_eq!(list_to_vec(&Object::Cons(
         Rc::new(Object::Number(1)),
         Rc::new(Object::Cons(
             Rc::new(Object::Number(2)),
@@ -133,9 +583,43 @@ fn list_to_vec_test() {
                 Rc::new(Object::Nil)
             ))
         ))
-    ))).expect("must not happpen"), vec![
+    )).expect("must not happpen"), vec![
         Rc::new(Object::Number(1)),
         Rc::new(Object::Number(2)),
         Rc::new(Object::Number(3))
     ]);
 }
+
+#[test]
+fn equality_test() {
+    let list = |objs: &[Object]| {
+        vec_to_list(&objs.iter().cloned().map(Rc::new).collect::<Vec<_>>())
+    };
+    let xs = Rc::new(list(&[number(1), symbol("a"), Object::Str("s".to_string())]));
+    let ys = Rc::new(list(&[number(1), symbol("a"), Object::Str("s".to_string())]));
+    assert!(xs.is_eq(&xs));
+    assert!(!xs.is_eq(&ys));
+    assert!(!xs.is_eqv(&ys));
+    assert!(xs.is_equal(&ys));
+    assert!(symbol("a").is_eq(&symbol("a")));
+    assert!(!Object::Str("s".to_string()).is_eqv(&Object::Str("s".to_string())));
+    assert!(Object::Float(f64::NAN).is_eqv(&Object::Float(f64::NAN)));
+    assert!(!Object::Float(0.0).is_eqv(&Object::Float(-0.0)));
+    assert!(!number(1).is_equal(&Object::Float(1.0)));
+    let f = Object::Func(Rc::new(vec![]), Rc::new(Env::new()));
+    assert!(!f.is_equal(&f.clone()));
+    assert!(f.is_equal(&f));
+}
+
+#[test]
+fn cyclic_equality_test() {
+    let cyclic = || {
+        let v = Rc::new(vector(vec![]));
+        v.to_vector().unwrap().borrow_mut().push(v.clone());
+        v
+    };
+    let (v1, v2) = (cyclic(), cyclic());
+    assert!(v1.is_equal(&v2));
+    assert!(!v1.is_eqv(&v2));
+    assert!(!v1.is_equal(&vector(vec![v2.clone(), v2.clone()])));
+}
@@ -0,0 +1,254 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::rc::Rc;
+use crate::object::Object;
+
+// Persistent hash map: a hash array mapped trie keyed by `equal?`.
+// Updates copy only the path to the changed entry, so older versions
+// stay valid and share the rest of the trie.
+#[derive(Debug, Clone)]
+pub struct PersistentMap {
+    len: usize,
+    root: Rc<Node>
+}
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+#[derive(Debug, Clone)]
+struct Node {
+    bitmap: u32,
+    entries: Vec<Entry>
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    Leaf(u64, Rc<Object>, Rc<Object>),
+    // Keys whose hashes are the same in all 64 bits.
+    Collision(u64, Rc<Vec<(Rc<Object>, Rc<Object>)>>),
+    Node(Rc<Node>)
+}
+
+fn hash(key: &Object) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash_equal(&mut hasher);
+    hasher.finish()
+}
+
+fn bitpos(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+impl Node {
+    fn empty() -> Self {
+        Node { bitmap: 0, entries: vec![] }
+    }
+
+    fn index(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    fn get(&self, hash: u64, shift: u32, key: &Object) -> Option<&Rc<Object>> {
+        let bit = bitpos(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &self.entries[self.index(bit)] {
+            Entry::Leaf(_, k, v) => if k.is_equal(key) { Some(v) } else { None },
+            Entry::Collision(_, pairs) => {
+                pairs.iter().find(|(k, _)| k.is_equal(key)).map(|(_, v)| v)
+            }
+            Entry::Node(node) => node.get(hash, shift + BITS, key)
+        }
+    }
+
+    // Returns the new node and whether the key was added.
+    fn assoc(&self, hash: u64, shift: u32, key: Rc<Object>, value: Rc<Object>) -> (Node, bool) {
+        let bit = bitpos(hash, shift);
+        let i = self.index(bit);
+        let mut node = self.clone();
+        if self.bitmap & bit == 0 {
+            node.bitmap |= bit;
+            node.entries.insert(i, Entry::Leaf(hash, key, value));
+            return (node, true);
+        }
+        let (entry, added) = match &self.entries[i] {
+            Entry::Leaf(h, k, _) if k.is_equal(&key) => (Entry::Leaf(*h, k.clone(), value), false),
+            Entry::Leaf(h, k, v) if *h == hash => {
+                let pairs = vec![(k.clone(), v.clone()), (key, value)];
+                (Entry::Collision(hash, Rc::new(pairs)), true)
+            }
+            Entry::Leaf(h, k, v) => {
+                let (sub, _) = Node::empty().assoc(*h, shift + BITS, k.clone(), v.clone());
+                let (sub, _) = sub.assoc(hash, shift + BITS, key, value);
+                (Entry::Node(Rc::new(sub)), true)
+            }
+            Entry::Collision(h, pairs) if *h == hash => {
+                let mut pairs = pairs.as_ref().clone();
+                let added = match pairs.iter().position(|(k, _)| k.is_equal(&key)) {
+                    Some(j) => {
+                        pairs[j].1 = value;
+                        false
+                    }
+                    None => {
+                        pairs.push((key, value));
+                        true
+                    }
+                };
+                (Entry::Collision(hash, Rc::new(pairs)), added)
+            }
+            Entry::Collision(h, pairs) => {
+                let sub = Node {
+                    bitmap: bitpos(*h, shift + BITS),
+                    entries: vec![Entry::Collision(*h, pairs.clone())]
+                };
+                let (sub, _) = sub.assoc(hash, shift + BITS, key, value);
+                (Entry::Node(Rc::new(sub)), true)
+            }
+            Entry::Node(sub) => {
+                let (sub, added) = sub.assoc(hash, shift + BITS, key, value);
+                (Entry::Node(Rc::new(sub)), added)
+            }
+        };
+        node.entries[i] = entry;
+        (node, added)
+    }
+
+    // Returns None if the key is not present.
+    fn dissoc(&self, hash: u64, shift: u32, key: &Object) -> Option<Node> {
+        let bit = bitpos(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let i = self.index(bit);
+        let entry = match &self.entries[i] {
+            Entry::Leaf(_, k, _) if k.is_equal(key) => None,
+            Entry::Leaf(..) => return None,
+            Entry::Collision(h, pairs) => {
+                let j = pairs.iter().position(|(k, _)| k.is_equal(key))?;
+                let mut pairs = pairs.as_ref().clone();
+                pairs.remove(j);
+                if pairs.len() == 1 {
+                    let (k, v) = pairs.pop().unwrap();
+                    Some(Entry::Leaf(*h, k, v))
+                } else {
+                    Some(Entry::Collision(*h, Rc::new(pairs)))
+                }
+            }
+            Entry::Node(sub) => {
+                let sub = sub.dissoc(hash, shift + BITS, key)?;
+                match sub.entries.as_slice() {
+                    [] => None,
+                    [Entry::Node(_)] => Some(Entry::Node(Rc::new(sub))),
+                    [entry] => Some(entry.clone()),
+                    _ => Some(Entry::Node(Rc::new(sub)))
+                }
+            }
+        };
+        let mut node = self.clone();
+        match entry {
+            Some(entry) => node.entries[i] = entry,
+            None => {
+                node.bitmap &= !bit;
+                node.entries.remove(i);
+            }
+        }
+        Some(node)
+    }
+
+    fn collect(&self, ret: &mut Vec<(Rc<Object>, Rc<Object>)>) {
+        for entry in &self.entries {
+            match entry {
+                Entry::Leaf(_, k, v) => ret.push((k.clone(), v.clone())),
+                Entry::Collision(_, pairs) => ret.extend(pairs.iter().cloned()),
+                Entry::Node(node) => node.collect(ret)
+            }
+        }
+    }
+}
+
+impl Default for PersistentMap {
+    fn default() -> Self {
+        PersistentMap::new()
+    }
+}
+
+impl PersistentMap {
+    pub fn new() -> Self {
+        PersistentMap { len: 0, root: Rc::new(Node::empty()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &Object) -> Option<Rc<Object>> {
+        self.root.get(hash(key), 0, key).cloned()
+    }
+
+    pub fn assoc(&self, key: Rc<Object>, value: Rc<Object>) -> Self {
+        let (root, added) = self.root.assoc(hash(&key), 0, key, value);
+        PersistentMap {
+            len: if added { self.len + 1 } else { self.len },
+            root: Rc::new(root)
+        }
+    }
+
+    pub fn dissoc(&self, key: &Object) -> Self {
+        match self.root.dissoc(hash(key), 0, key) {
+            Some(root) => PersistentMap { len: self.len - 1, root: Rc::new(root) },
+            None => self.clone()
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(Rc<Object>, Rc<Object>)> {
+        let mut ret = Vec::with_capacity(self.len);
+        self.root.collect(&mut ret);
+        ret
+    }
+}
+
+#[test]
+fn pmap_test() {
+    let num = |n| Rc::new(Object::Number(n));
+    let mut maps = vec![PersistentMap::new()];
+    for i in 0..1000 {
+        let m = maps.last().unwrap().assoc(num(i), num(i * i));
+        maps.push(m);
+    }
+    for (n, m) in maps.iter().enumerate() {
+        assert_eq!(m.len(), n);
+    }
+    let m = maps.last().unwrap();
+    assert_eq!(m.get(&Object::Number(30)), Some(num(900)));
+    assert_eq!(maps[30].get(&Object::Number(30)), None);
+    assert_eq!(m.assoc(num(30), num(0)).len(), 1000);
+    assert_eq!(m.get(&Object::Number(30)), Some(num(900)));
+
+    let mut m = m.clone();
+    for i in (0..1000).step_by(2) {
+        m = m.dissoc(&Object::Number(i));
+    }
+    assert_eq!(m.len(), 500);
+    assert_eq!(m.get(&Object::Number(2)), None);
+    assert_eq!(m.get(&Object::Number(3)), Some(num(9)));
+    assert_eq!(m.dissoc(&Object::Number(2)).len(), 500);
+    assert_eq!(maps[1000].entries().len(), 1000);
+}
+
+#[test]
+fn pmap_collision_test() {
+    let root = Node::empty();
+    let (root, _) = root.assoc(7, 0, Rc::new(Object::Number(1)), Rc::new(Object::T));
+    let (root, added) = root.assoc(7, 0, Rc::new(Object::Number(2)), Rc::new(Object::Nil));
+    assert!(added);
+    assert_eq!(root.get(7, 0, &Object::Number(1)), Some(&Rc::new(Object::T)));
+    assert_eq!(root.get(7, 0, &Object::Number(2)), Some(&Rc::new(Object::Nil)));
+    let root = root.dissoc(7, 0, &Object::Number(1)).unwrap();
+    assert_eq!(root.get(7, 0, &Object::Number(1)), None);
+    assert!(matches!(root.entries[0], Entry::Leaf(..)));
+}
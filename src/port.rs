@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::iter;
+use std::path::Path;
+use std::result;
+use std::str;
+use crate::error::{Error, error};
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Port {
+    Input(RefCell<Input>),
+    Output(RefCell<Output>)
+}
+
+#[derive(Debug)]
+pub enum Input {
+    // Standard input, and a char that was read but not used.
+    Stdin(Option<char>),
+    // The contents of a file or string, and the position of the next char.
+    Chars(Vec<char>, usize),
+    Closed
+}
+
+#[derive(Debug)]
+pub enum Output {
+    Stdout,
+    File(BufWriter<File>),
+    Buffer(String),
+    Closed
+}
+
+// The characters of standard input. Bytes are taken from its buffer one at
+// a time, so that little past what is being read is consumed.
+fn stdin_chars() -> impl Iterator<Item = char> {
+    let mut bytes = io::stdin().lock().bytes().map_while(result::Result::ok);
+    iter::from_fn(move || {
+        let mut buf = vec![bytes.next()?];
+        loop {
+            match str::from_utf8(&buf) {
+                Ok(s) => return s.chars().next(),
+                Err(e) if e.error_len().is_none() => buf.push(bytes.next()?),
+                Err(_) => return Some(char::REPLACEMENT_CHARACTER)
+            }
+        }
+    })
+}
+
+fn io_error(path: &Path, e: io::Error) -> Error {
+    error(&format!("Can't open {}: {}", path.display(), e))
+}
+
+impl Port {
+    pub fn stdin() -> Self {
+        Port::Input(RefCell::new(Input::Stdin(None)))
+    }
+
+    pub fn stdout() -> Self {
+        Port::Output(RefCell::new(Output::Stdout))
+    }
+
+    pub fn input_string(s: &str) -> Self {
+        Port::Input(RefCell::new(Input::Chars(s.chars().collect(), 0)))
+    }
+
+    // An output port that collects what is written, see `contents`.
+    pub fn output_string() -> Self {
+        Port::Output(RefCell::new(Output::Buffer(String::new())))
+    }
+
+    pub fn open_input_file(path: &Path) -> Result<Self> {
+        let s = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::input_string(&s))
+    }
+
+    pub fn open_output_file(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::Output(RefCell::new(Output::File(BufWriter::new(file)))))
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(self, Port::Input(_))
+    }
+
+    // Calls `f` with the chars left in an input port. Along with its value,
+    // `f` returns the last char it took if it did not use it, and that
+    // char is read again next time.
+    pub fn read_with<T>(&self, f: impl FnOnce(&mut dyn Iterator<Item = char>) -> (T, Option<char>)) -> Result<T> {
+        let mut input = match self {
+            Port::Input(input) => input.borrow_mut(),
+            Port::Output(_) => return Err(error("Not an input port"))
+        };
+        match *input {
+            Input::Stdin(ref mut unread) => {
+                let (ret, rest) = f(&mut unread.take().into_iter().chain(stdin_chars()));
+                *unread = rest;
+                Ok(ret)
+            }
+            Input::Chars(ref chars, ref mut pos) => {
+                let (ret, rest) = f(&mut iter::from_fn(|| {
+                    let c = chars.get(*pos)?;
+                    *pos += 1;
+                    Some(*c)
+                }));
+                if rest.is_some() {
+                    *pos -= 1;
+                }
+                Ok(ret)
+            }
+            Input::Closed => Err(error("Port is closed"))
+        }
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>> {
+        self.read_with(|chars| (chars.next(), None))
+    }
+
+    // The next line without its line ending, or None at the end of input.
+    pub fn read_line(&self) -> Result<Option<String>> {
+        self.read_with(|chars| {
+            let mut line = String::new();
+            for c in chars {
+                if c == '\n' {
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                    return (Some(line), None);
+                }
+                line.push(c);
+            }
+            (if line.is_empty() { None } else { Some(line) }, None)
+        })
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<()> {
+        let mut output = match self {
+            Port::Output(output) => output.borrow_mut(),
+            Port::Input(_) => return Err(error("Not an output port"))
+        };
+        let ret = match *output {
+            Output::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(s.as_bytes()).and_then(|_| stdout.flush())
+            }
+            Output::File(ref mut file) => file.write_all(s.as_bytes()),
+            Output::Buffer(ref mut buf) => {
+                buf.push_str(s);
+                Ok(())
+            }
+            Output::Closed => return Err(error("Port is closed"))
+        };
+        ret.map_err(|e| error(&format!("Can't write: {}", e)))
+    }
+
+    // What has been written to a string port so far.
+    pub fn contents(&self) -> Option<String> {
+        match self {
+            Port::Output(output) => match *output.borrow() {
+                Output::Buffer(ref buf) => Some(buf.clone()),
+                _ => None
+            },
+            Port::Input(_) => None
+        }
+    }
+
+    // Closes the port, flushing a file. Closing a port twice is allowed.
+    pub fn close(&self) -> Result<()> {
+        match self {
+            Port::Input(input) => {
+                *input.borrow_mut() = Input::Closed;
+                Ok(())
+            }
+            Port::Output(output) => {
+                match output.replace(Output::Closed) {
+                    Output::File(mut file) => file.flush().map_err(|e| {
+                        error(&format!("Can't write: {}", e))
+                    }),
+                    _ => Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn port_test() {
+    let port = Port::input_string("ab\r\ncd\n\nef");
+    assert_eq!(port.read_char().unwrap(), Some('a'));
+    assert_eq!(port.read_line().unwrap(), Some("b".to_string()));
+    assert_eq!(port.read_line().unwrap(), Some("cd".to_string()));
+    assert_eq!(port.read_line().unwrap(), Some("".to_string()));
+    assert_eq!(port.read_line().unwrap(), Some("ef".to_string()));
+    assert_eq!(port.read_line().unwrap(), None);
+    assert!(port.write_str("x").is_err());
+    port.close().unwrap();
+    assert!(port.read_char().is_err());
+
+    let path = std::env::temp_dir().join(format!("rusp-port-test-{}", std::process::id()));
+    let port = Port::open_output_file(&path).unwrap();
+    port.write_str("line\n").unwrap();
+    port.close().unwrap();
+    assert!(port.write_str("x").is_err());
+    let port = Port::open_input_file(&path).unwrap();
+    assert_eq!(port.read_line().unwrap(), Some("line".to_string()));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,60 @@
+;;; The prelude: list functions written in terms of the core forms, and
+;;; evaluated when the interpreter starts (see prelude.rs). Faster natives
+;;; for long lists are named with a leading %, as in %map.
+
+(define (list . xs) xs)
+
+(define (length xs)
+  (if (null xs) 0 (+ 1 (length (cdr xs)))))
+
+(define (%nth i n xs)
+  (if (atom xs)
+      (error "Index out of range:" n)
+      (if (= i 0) (car xs) (%nth (- i 1) n (cdr xs)))))
+
+(define (nth n xs)
+  (if (< n 0) (error "Index out of range:" n) (%nth n n xs)))
+
+;; (fold f init '(a b c)) is (f c (f b (f a init))).
+(define (fold f acc xs)
+  (if (null xs) acc (fold f (f (car xs) acc) (cdr xs))))
+
+(define (reverse xs) (fold cons nil xs))
+
+(define (%append2 xs ys)
+  (if (null xs) ys (cons (car xs) (%append2 (cdr xs) ys))))
+
+(define (%append-all lists)
+  (if (null lists)
+      nil
+      (if (null (cdr lists))
+          (car lists)
+          (%append2 (car lists) (%append-all (cdr lists))))))
+
+(define (append . lists) (%append-all lists))
+
+;; Applications evaluate their arguments right to left, so `f` is called
+;; first through a lambda to apply it from left to right.
+(define (map f xs)
+  (if (null xs)
+      nil
+      ((lambda (y) (cons y (map f (cdr xs)))) (f (car xs)))))
+
+(define (filter pred xs)
+  (if (null xs)
+      nil
+      (if (pred (car xs))
+          (cons (car xs) (filter pred (cdr xs)))
+          (filter pred (cdr xs)))))
+
+(define (assoc key alist)
+  (if (null alist)
+      nil
+      (if (equal? key (car (car alist)))
+          (car alist)
+          (assoc key (cdr alist)))))
+
+(define (member x xs)
+  (if (null xs)
+      nil
+      (if (equal? x (car xs)) xs (member x (cdr xs)))))
@@ -0,0 +1,47 @@
+use std::result;
+use crate::error::Error;
+use crate::reader;
+use crate::vm::Vm;
+
+pub type Result<T> = result::Result<T, Error>;
+
+const PRELUDE: &str = include_str!("prelude.lisp");
+
+// Defines the prelude functions next to the natives, where modules see
+// them too. Interpreters that should only have the native functions can
+// skip this.
+pub fn load(vm: &mut Vm) -> Result<()> {
+    let prev = vm.set_namespace(vm.namespace().root());
+    let ret = reader::read(PRELUDE.chars()).try_for_each(|expr| vm.eval(&expr?).map(|_| ()));
+    vm.set_namespace(prev);
+    ret
+}
+
+#[test]
+fn prelude_test() {
+    let mut vm = Vm::default();
+    load(&mut vm).expect("prelude must load");
+    let mut eval = |src: &str| {
+        let expr = reader::read_string(src).unwrap().unwrap();
+        vm.eval(&expr).map(|v| v.to_string()).map_err(|e| e.to_string())
+    };
+    assert_eq!(eval("(list 1 2 3)"), Ok("(1 2 3)".to_string()));
+    assert_eq!(eval("(list)"), Ok("nil".to_string()));
+    assert_eq!(eval("(length '(a b c))"), Ok("3".to_string()));
+    assert_eq!(eval("(nth 1 '(a b c))"), Ok("b".to_string()));
+    assert_eq!(eval("(nth 3 '(a b c))"), Err("Index out of range: 3".to_string()));
+    assert_eq!(eval("(nth -1 '(a b c))"), Err("Index out of range: -1".to_string()));
+    assert_eq!(eval("(fold + 0 '(1 2 3))"), Ok("6".to_string()));
+    assert_eq!(eval("(reverse '(1 2 3))"), Ok("(3 2 1)".to_string()));
+    assert_eq!(eval("(append '(1) '(2 3) nil '(4))"), Ok("(1 2 3 4)".to_string()));
+    assert_eq!(eval("(append)"), Ok("nil".to_string()));
+    assert_eq!(eval("(map (lambda (x) (* x x)) '(1 2 3))"), Ok("(1 4 9)".to_string()));
+    assert_eq!(eval("(filter (lambda (x) (< x 2)) '(1 2 0))"), Ok("(1 0)".to_string()));
+    assert_eq!(eval("(assoc '(b) '((a . 1) ((b) . 2)))"), Ok("((b) . 2)".to_string()));
+    assert_eq!(eval("(member 2 '(1 2 3))"), Ok("(2 3)".to_string()));
+    assert_eq!(eval("(member 4 '(1 2 3))"), Ok("nil".to_string()));
+    assert_eq!(eval("(eq? map %map)"), Ok("nil".to_string()));
+    assert_eq!(eval("((lambda (a . b) b) 1)"), Ok("nil".to_string()));
+    assert_eq!(eval("((lambda (a b) a) 1)"), Err("too less arguments".to_string()));
+    assert_eq!(eval("((lambda (a) a) 1 2)"), Err("too many arguments".to_string()));
+}
@@ -0,0 +1,197 @@
+use std::iter::FromIterator;
+use std::rc::Rc;
+use crate::object::Object;
+
+// Persistent vector: a 32-way trie with the last (up to 32) elements kept
+// in a separate tail, as in Clojure. Updates copy only the path to the
+// changed leaf.
+#[derive(Debug, Clone)]
+pub struct PersistentVector {
+    len: usize,
+    shift: u32,
+    root: Rc<Node>,
+    tail: Rc<Vec<Rc<Object>>>
+}
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Branch(Vec<Rc<Node>>),
+    Leaf(Rc<Vec<Rc<Object>>>)
+}
+
+impl Node {
+    fn children(&self) -> &[Rc<Node>] {
+        match self {
+            Node::Branch(children) => children,
+            Node::Leaf(_) => unreachable!("leaf found above the bottom level")
+        }
+    }
+
+    fn elems(&self) -> &[Rc<Object>] {
+        match self {
+            Node::Leaf(elems) => elems,
+            Node::Branch(_) => unreachable!("branch found at the bottom level")
+        }
+    }
+}
+
+fn new_path(level: u32, node: Rc<Node>) -> Rc<Node> {
+    if level == 0 {
+        node
+    } else {
+        Rc::new(Node::Branch(vec![new_path(level - BITS, node)]))
+    }
+}
+
+impl Default for PersistentVector {
+    fn default() -> Self {
+        PersistentVector::new()
+    }
+}
+
+impl PersistentVector {
+    pub fn new() -> Self {
+        PersistentVector {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(Node::Branch(vec![])),
+            tail: Rc::new(vec![])
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of elements stored in the trie rather than the tail.
+    fn tail_offset(&self) -> usize {
+        if self.len < WIDTH { 0 } else { ((self.len - 1) >> BITS) << BITS }
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Rc<Object>> {
+        if i >= self.len {
+            return None;
+        }
+        if i >= self.tail_offset() {
+            return self.tail.get(i & MASK);
+        }
+        let mut node = &self.root;
+        let mut level = self.shift;
+        while level > 0 {
+            node = &node.children()[(i >> level) & MASK];
+            level -= BITS;
+        }
+        node.elems().get(i & MASK)
+    }
+
+    pub fn push(&self, obj: Rc<Object>) -> Self {
+        if self.len - self.tail_offset() < WIDTH {
+            let mut tail = self.tail.as_ref().clone();
+            tail.push(obj);
+            return PersistentVector { len: self.len + 1, tail: Rc::new(tail), ..self.clone() };
+        }
+        let tail_node = Rc::new(Node::Leaf(self.tail.clone()));
+        let (root, shift) = if (self.len >> BITS) > (1 << self.shift) {
+            let root = Node::Branch(vec![self.root.clone(), new_path(self.shift, tail_node)]);
+            (Rc::new(root), self.shift + BITS)
+        } else {
+            (self.push_tail(self.shift, &self.root, tail_node), self.shift)
+        };
+        PersistentVector { len: self.len + 1, shift, root, tail: Rc::new(vec![obj]) }
+    }
+
+    fn push_tail(&self, level: u32, parent: &Node, tail_node: Rc<Node>) -> Rc<Node> {
+        let i = ((self.len - 1) >> level) & MASK;
+        let mut children = parent.children().to_vec();
+        let child = if level == BITS {
+            tail_node
+        } else {
+            match children.get(i) {
+                Some(child) => self.push_tail(level - BITS, child, tail_node),
+                None => new_path(level - BITS, tail_node)
+            }
+        };
+        if i < children.len() {
+            children[i] = child;
+        } else {
+            children.push(child);
+        }
+        Rc::new(Node::Branch(children))
+    }
+
+    // Replaces the i-th element; `i == len` appends. Returns None if `i`
+    // is out of range.
+    pub fn assoc(&self, i: usize, obj: Rc<Object>) -> Option<Self> {
+        if i == self.len {
+            return Some(self.push(obj));
+        }
+        if i > self.len {
+            return None;
+        }
+        if i >= self.tail_offset() {
+            let mut tail = self.tail.as_ref().clone();
+            tail[i & MASK] = obj;
+            return Some(PersistentVector { tail: Rc::new(tail), ..self.clone() });
+        }
+        let root = Self::assoc_in(self.shift, &self.root, i, obj);
+        Some(PersistentVector { root, ..self.clone() })
+    }
+
+    fn assoc_in(level: u32, node: &Node, i: usize, obj: Rc<Object>) -> Rc<Node> {
+        if level == 0 {
+            let mut elems = node.elems().to_vec();
+            elems[i & MASK] = obj;
+            return Rc::new(Node::Leaf(Rc::new(elems)));
+        }
+        let mut children = node.children().to_vec();
+        let j = (i >> level) & MASK;
+        children[j] = Self::assoc_in(level - BITS, &children[j], i, obj);
+        Rc::new(Node::Branch(children))
+    }
+
+    pub fn to_vec(&self) -> Vec<Rc<Object>> {
+        (0..self.len).map(|i| self.get(i).unwrap().clone()).collect()
+    }
+}
+
+impl FromIterator<Rc<Object>> for PersistentVector {
+    fn from_iter<I: IntoIterator<Item = Rc<Object>>>(iter: I) -> Self {
+        iter.into_iter().fold(PersistentVector::new(), |v, obj| v.push(obj))
+    }
+}
+
+#[test]
+fn pvector_test() {
+    let num = |n| Rc::new(Object::Number(n));
+    let mut vs = vec![PersistentVector::new()];
+    for i in 0..2000 {
+        let v = vs.last().unwrap().push(num(i));
+        vs.push(v);
+    }
+    let v = vs.last().unwrap();
+    assert_eq!(v.len(), 2000);
+    for i in 0..2000 {
+        assert_eq!(v.get(i as usize), Some(&num(i)));
+    }
+    assert_eq!(v.get(2000), None);
+    assert_eq!(vs[33].len(), 33);
+    assert_eq!(vs[33].get(32), Some(&num(32)));
+    assert_eq!(vs[33].get(33), None);
+
+    let w = v.assoc(5, num(-5)).unwrap().assoc(1999, num(-1999)).unwrap();
+    assert_eq!(w.get(5), Some(&num(-5)));
+    assert_eq!(w.get(1999), Some(&num(-1999)));
+    assert_eq!(v.get(5), Some(&num(5)));
+    assert_eq!(v.assoc(2001, num(0)).map(|v| v.len()), None);
+    assert_eq!(v.assoc(2000, num(0)).map(|v| v.len()), Some(2001));
+    let xs: PersistentVector = (0..40).map(num).collect();
+    assert_eq!(xs.to_vec(), (0..40).map(num).collect::<Vec<_>>());
+}
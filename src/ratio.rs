@@ -0,0 +1,166 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use crate::bigint::BigInt;
+
+// Exact rational number, always kept in lowest terms with a positive
+// denominator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    num: BigInt,
+    den: BigInt
+}
+
+impl Ratio {
+    // Panics if `den` is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Self {
+        assert!(!den.is_zero(), "zero denominator");
+        let g = num.gcd(&den);
+        let (num, _) = num.div_rem(&g).unwrap();
+        let (den, _) = den.div_rem(&g).unwrap();
+        if den.is_negative() {
+            Ratio { num: -&num, den: -&den }
+        } else {
+            Ratio { num, den }
+        }
+    }
+
+    pub fn from_integer(n: BigInt) -> Self {
+        Ratio { num: n, den: BigInt::from_i64(1) }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == BigInt::from_i64(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    // The parts may be too large for f64 even when their quotient is not,
+    // so the quotient is taken exactly, scaled by a power of two to 55 or
+    // more bits. A nonzero remainder is kept in the lowest bit so that
+    // converting the quotient rounds correctly.
+    pub fn to_f64(&self) -> f64 {
+        let shift = self.den.bit_len() as i64 - self.num.bit_len() as i64 + 55;
+        let (num, den) = if shift > 0 {
+            (self.num.shl(shift as u64), self.den.clone())
+        } else {
+            (self.num.clone(), self.den.shl(shift.unsigned_abs()))
+        };
+        let (mut q, r) = num.div_rem(&den).unwrap();
+        if !r.is_zero() && q.is_even() {
+            let one = BigInt::from_i64(if q.is_negative() { -1 } else { 1 });
+            q = &q + &one;
+        }
+        scale(q.to_f64(), -shift)
+    }
+}
+
+// x * 2^exp, in steps so that the power of two itself stays finite.
+fn scale(mut x: f64, mut exp: i64) -> f64 {
+    let step = 1000;
+    while exp > step {
+        x *= 2f64.powi(step as i32);
+        exp -= step;
+    }
+    while exp < -step {
+        x *= 2f64.powi(-step as i32);
+        exp += step;
+    }
+    x * 2f64.powi(exp as i32)
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Ratio) -> Ordering {
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Ratio) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &Ratio {
+    type Output = Ratio;
+
+    fn neg(self) -> Ratio {
+        Ratio { num: -&self.num, den: self.den.clone() }
+    }
+}
+
+impl Add for &Ratio {
+    type Output = Ratio;
+
+    fn add(self, other: &Ratio) -> Ratio {
+        Ratio::new(&(&self.num * &other.den) + &(&other.num * &self.den),
+                   &self.den * &other.den)
+    }
+}
+
+impl Sub for &Ratio {
+    type Output = Ratio;
+
+    fn sub(self, other: &Ratio) -> Ratio {
+        self + &-other
+    }
+}
+
+impl Mul for &Ratio {
+    type Output = Ratio;
+
+    fn mul(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.num, &self.den * &other.den)
+    }
+}
+
+// Panics if `other` is zero.
+impl Div for &Ratio {
+    type Output = Ratio;
+
+    fn div(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.den, &self.den * &other.num)
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+#[test]
+fn ratio_test() {
+    let ratio = |n, d| Ratio::new(BigInt::from_i64(n), BigInt::from_i64(d));
+    assert_eq!(ratio(6, -8), ratio(-3, 4));
+    assert_eq!(ratio(6, -8).to_string(), "-3/4");
+    assert_eq!(&ratio(1, 3) + &ratio(1, 6), ratio(1, 2));
+    assert_eq!(&ratio(1, 3) - &ratio(1, 3), ratio(0, 1));
+    assert_eq!(&ratio(2, 3) * &ratio(3, 4), ratio(1, 2));
+    assert_eq!(&ratio(2, 3) / &ratio(-4, 3), ratio(-1, 2));
+    assert!(ratio(1, 3) < ratio(1, 2));
+    assert!(ratio(-1, 2) < ratio(-1, 3));
+    assert!(ratio(4, 2).is_integer());
+    assert_eq!(ratio(1, 3).to_f64(), 1.0 / 3.0);
+    assert_eq!(ratio(-2, 3).to_f64(), -2.0 / 3.0);
+
+    // (10^400 + 1) / 10^399 and its inverse, whose parts overflow f64.
+    let pow10 = |n| (0..n).fold(BigInt::from_i64(1), |acc, _| &acc * &BigInt::from_i64(10));
+    let x = Ratio::new(&pow10(400) + &BigInt::from_i64(1), pow10(399));
+    assert_eq!(x.to_f64(), 10.0);
+    let y = Ratio::new(pow10(399), &pow10(400) + &BigInt::from_i64(1));
+    assert_eq!(y.to_f64(), 0.1);
+    let z = Ratio::new(pow10(400), &pow10(90) + &BigInt::from_i64(7));
+    assert_eq!(z.to_f64(), f64::INFINITY);
+    assert_eq!(Ratio::new(BigInt::from_i64(-1), pow10(320)).to_f64(), -1e-320);
+}
@@ -1,16 +1,32 @@
 use std::char;
+use std::iter::Peekable;
 use std::rc::Rc;
-use crate::error::{Error, error};
-use crate::object::Object;
+use std::result;
+use crate::error::{Error, error, incomplete};
+use crate::number;
+use crate::object::{self, Object};
+use crate::pmap::PersistentMap;
+use crate::pvector::PersistentVector;
+use crate::symbol::{self, Symbol};
+
+pub type Result<T> = result::Result<T, Error>;
 
 struct ReaderIterator<I: Iterator<Item = char>> {
-    iter: I,
+    iter: Peekable<I>,
     peek: Option<char>
 }
 
 impl <I: Iterator<Item = char>> ReaderIterator<I> {
     fn new(iter: I) -> Self {
-        ReaderIterator { iter, peek: None }
+        ReaderIterator { iter: iter.peekable(), peek: None }
+    }
+
+    // Skips a `#!` line at the very start, so scripts can be run directly.
+    fn skip_shebang(mut self) -> Self {
+        if self.peek_char() == Some('#') && self.iter.peek() == Some(&'!') {
+            self.drop_while(|c| *c != '\n');
+        }
+        self
     }
 
     fn next_char(&mut self) -> Option<char> {
@@ -18,7 +34,7 @@ impl <I: Iterator<Item = char>> ReaderIterator<I> {
     }
 
     fn peek_char(&mut self) -> Option<char> {
-        if self.peek == None {
+        if self.peek.is_none() {
             self.peek = self.next_char()
         }
         self.peek
@@ -54,6 +70,7 @@ impl <I: Iterator<Item = char>> ReaderIterator<I> {
             if !f(&c) {
                 return;
             }
+            self.clear();
         }
         for c in self.iter.by_ref() {
             if !f(&c) {
@@ -63,92 +80,366 @@ impl <I: Iterator<Item = char>> ReaderIterator<I> {
         }
     }
 
-    fn skip_whitespaces(&mut self) -> Option<char> {
-        self.drop_while(|c| c.is_whitespace());
-        self.peek_char()
+    // Skips whitespace and comments, and returns the next character.
+    fn skip_whitespaces(&mut self) -> Result<Option<char>> {
+        loop {
+            self.drop_while(|c| c.is_whitespace());
+            match self.peek_char() {
+                Some(';') => self.drop_while(|c| *c != '\n'),
+                Some('#') => match self.iter.peek() {
+                    Some('|') => {
+                        self.clear();
+                        self.next_char();
+                        self.skip_block_comment()?;
+                    }
+                    Some(';') => {
+                        self.clear();
+                        self.next_char();
+                        match self.next() {
+                            Some(datum) => datum?,
+                            None => return Err(incomplete("Unexpected end of input after #;"))
+                        };
+                    }
+                    _ => return Ok(Some('#'))
+                },
+                c => return Ok(c)
+            }
+        }
     }
 
-    fn read_number(&mut self) -> Object {
-        let negative = self.peek.unwrap_or('0') == '-';
-        if negative {
-            self.clear();
+    // Skips the rest of a `#| ... |#` comment, which may be nested.
+    fn skip_block_comment(&mut self) -> Result<()> {
+        let mut depth = 1;
+        let mut prev = None;
+        while let Some(c) = self.next_char() {
+            match (prev, c) {
+                (Some('|'), '#') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    prev = None;
+                }
+                (Some('#'), '|') => {
+                    depth += 1;
+                    prev = None;
+                }
+                _ => prev = Some(c)
+            }
         }
-        let ds = self.read_while(|c| c.is_digit(10));
-        let num = ds.parse::<i32>().unwrap();
-        Object::Number(if negative { -num } else { num })
+        Err(incomplete("Unterminated block comment"))
     }
 
-    fn read_symbol(&mut self) -> Object {
-        let name = self.read_while(|c| {
+    fn read_token(&mut self) -> String {
+        self.read_while(|c| {
             match c {
-                '(' | ')' | '\'' | '"' | ',' => false,
+                '(' | ')' | '[' | ']' | '{' | '}' | '\'' | '"' | ',' | ';' | '|' => false,
                 _ => !c.is_whitespace()
             }
-        });
-        match name.as_str() {
+        })
+    }
+
+    fn read_number(&mut self) -> Result<Object> {
+        let token = self.read_token();
+        parse_number(&token)
+    }
+
+    // `:|a b|` is the keyword named "a b", as for symbols.
+    fn read_symbol(&mut self) -> Result<Object> {
+        let name = self.read_token();
+        Ok(match name.as_str() {
             "t" => Object::T,
             "nil" => Object::Nil,
-            _ => Object::Symbol(name)
+            ":" if self.peek == Some('|') => {
+                Object::Keyword(Symbol::intern(&self.read_delimited('|', "|symbol|")?))
+            }
+            _ => match name.strip_prefix(':') {
+                Some(key) if !key.is_empty() => Object::Keyword(Symbol::intern(key)),
+                _ => object::symbol(&name)
+            }
+        })
+    }
+
+    // Reads up to an unescaped `close`, which is consumed. A backslash
+    // introduces `\n`, `\t`, `\r` or `\x41;`, and stands for the char after
+    // it otherwise.
+    fn read_delimited(&mut self, close: char, what: &str) -> Result<String> {
+        let mut s = String::new();
+        self.clear();
+        loop {
+            match self.next_char() {
+                Some(c) if c == close => return Ok(s),
+                Some('\\') => {
+                    match self.next_char() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('x') => s.push(self.read_hex_escape()?),
+                        Some(c) => s.push(c),
+                        None => break
+                    }
+                }
+                Some(c) => s.push(c),
+                None => break
+            }
         }
+        Err(incomplete(&format!("Unterminated {}", what)))
     }
 
-    fn read_list(&mut self) -> Object {
+    fn read_hex_escape(&mut self) -> Result<char> {
+        let mut hex = String::new();
+        loop {
+            match self.next_char() {
+                Some(';') => break,
+                Some(c) => hex.push(c),
+                None => return Err(incomplete("Unterminated \\x escape"))
+            }
+        }
+        u32::from_str_radix(&hex, 16).ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| error(&format!("Invalid escape: \\x{};", hex)))
+    }
+
+    fn read_str(&mut self) -> Result<Object> {
+        Ok(Object::Str(self.read_delimited('"', "string")?))
+    }
+
+    // `|a b|` is the symbol named "a b", even where the name would
+    // otherwise read as something else, like `|nil|` or `|12|`.
+    fn read_bar_symbol(&mut self) -> Result<Object> {
+        Ok(object::symbol(&self.read_delimited('|', "|symbol|")?))
+    }
+
+    fn read_quote(&mut self) -> Result<Object> {
+        self.clear();
+        let datum = self.next().unwrap_or(Err(incomplete("Unexpected end of input after quote")))?;
+        Ok(object::vec_to_list(&[Rc::new(Object::Symbol(symbol::QUOTE)), Rc::new(datum)]))
+    }
+
+    // Reads elements up to `close`, which is consumed.
+    fn read_seq(&mut self, close: char) -> Result<Vec<Object>> {
         let mut elems: Vec<Object> = vec![];
         self.clear();
         loop {
-            let c = self.peek_char().unwrap();
-            if c == ')' {
+            match self.skip_whitespaces()? {
+                None => return Err(incomplete(&format!("Missing closing '{}'", close))),
+                Some(c) if c == close => {
+                    self.clear();
+                    return Ok(elems);
+                }
+                Some(c @ ')') | Some(c @ ']') | Some(c @ '}') => {
+                    return Err(error(&format!("Expected '{}' but found '{}'", close, c)));
+                }
+                Some(_) => elems.push(self.next().unwrap()?)
+            }
+        }
+    }
+
+    // Like `read_seq`, but a `.` before the last element makes it the
+    // tail of the list.
+    fn read_list(&mut self) -> Result<Object> {
+        let mut elems: Vec<Object> = vec![];
+        let mut tail = Object::Nil;
+        self.clear();
+        loop {
+            match self.skip_whitespaces()? {
+                None => return Err(incomplete("Missing closing ')'")),
+                Some(')') => {
+                    self.clear();
+                    break;
+                }
+                Some(c @ ']') | Some(c @ '}') => {
+                    return Err(error(&format!("Expected ')' but found '{}'", c)));
+                }
+                Some('.') => {
+                    let token = self.read_token();
+                    if token != "." {
+                        elems.push(parse_number(&token)?);
+                        continue;
+                    }
+                    tail = self.read_tail(elems.is_empty())?;
+                    break;
+                }
+                Some(_) => elems.push(self.next().unwrap()?)
+            }
+        }
+        Ok(elems.into_iter().rev().fold(tail, |acc, e| {
+            Object::Cons(Rc::new(e), Rc::new(acc))
+        }))
+    }
+
+    // Reads the element after a `.` and the closing paren.
+    fn read_tail(&mut self, first: bool) -> Result<Object> {
+        if first {
+            return Err(error("Nothing before '.' in list"));
+        }
+        let tail = match self.skip_whitespaces()? {
+            None => return Err(incomplete("Missing closing ')'")),
+            Some(')') => return Err(error("Nothing after '.' in list")),
+            Some(_) => self.next().unwrap()?
+        };
+        match self.skip_whitespaces()? {
+            None => Err(incomplete("Missing closing ')'")),
+            Some(')') => {
                 self.clear();
-                return elems.into_iter().rev().fold(Object::Nil, |acc, e| {
-                    Object::Cons(Rc::new(e), Rc::new(acc))
-                })
+                Ok(tail)
+            }
+            Some(_) => Err(error("More than one object after '.' in list"))
+        }
+    }
+
+    fn read_vector(&mut self, close: char) -> Result<Object> {
+        Ok(object::vector(self.read_seq(close)?.into_iter().map(Rc::new).collect()))
+    }
+
+    fn read_pvector(&mut self) -> Result<Object> {
+        let elems = self.read_seq(']')?;
+        Ok(Object::PVector(elems.into_iter().map(Rc::new).collect::<PersistentVector>()))
+    }
+
+    fn read_map(&mut self) -> Result<Object> {
+        let elems = self.read_seq('}')?;
+        if !elems.len().is_multiple_of(2) {
+            return Err(error("Map literal must have an even number of forms"));
+        }
+        let mut map = PersistentMap::new();
+        let mut elems = elems.into_iter().map(Rc::new);
+        while let (Some(k), Some(v)) = (elems.next(), elems.next()) {
+            map = map.assoc(k, v);
+        }
+        Ok(Object::Map(map))
+    }
+
+    // Reads `#\a`, `#\space` or `#\x41`. A name only continues after an
+    // alphanumeric character, so `#\(` is the open paren character.
+    fn read_char(&mut self) -> Result<Object> {
+        self.clear();
+        let c = self.next_char().ok_or(incomplete("Unexpected end of input after #\\"))?;
+        let rest = if c.is_alphanumeric() { self.read_token() } else { String::new() };
+        if rest.is_empty() {
+            return Ok(Object::Char(c));
+        }
+        let name = format!("{}{}", c, rest);
+        if let Some((_, c)) = object::CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+            return Ok(Object::Char(*c));
+        }
+        name.strip_prefix('x')
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .map(Object::Char)
+            .ok_or_else(|| error(&format!("Unknown character name: #\\{}", name)))
+    }
+
+    fn read_dispatch(&mut self) -> Result<Object> {
+        self.clear();
+        match self.peek_char() {
+            Some('\\') => self.read_char(),
+            Some('(') => self.read_vector(')'),
+            Some('[') => self.read_pvector(),
+            _ => {
+                let name = format!("#{}", self.read_token());
+                Ok(object::symbol(&name))
             }
-            elems.push(self.next().unwrap());
         }
     }
 }
 
 impl<I: Iterator<Item = char>> Iterator for ReaderIterator<I> {
-    type Item = Object;
+    type Item = Result<Object>;
 
-    fn next(&mut self) -> Option<Object> {
-        match self.skip_whitespaces()? {
-            '-' | '0' ... '9' => Some(self.read_number()),
-            '(' => Some(self.read_list()),
-            _ => Some(self.read_symbol())
-        }
+    fn next(&mut self) -> Option<Result<Object>> {
+        let c = match self.skip_whitespaces() {
+            Ok(c) => c?,
+            Err(e) => return Some(Err(e))
+        };
+        Some(match c {
+            '-' | '+' | '.' | '0'..='9' => self.read_number(),
+            '(' => self.read_list(),
+            '[' => self.read_vector(']'),
+            '{' => self.read_map(),
+            '#' => self.read_dispatch(),
+            '"' => self.read_str(),
+            '|' => self.read_bar_symbol(),
+            '\'' => self.read_quote(),
+            ')' | ']' | '}' => {
+                self.clear();
+                Err(error(&format!("Unexpected '{}'", c)))
+            }
+            _ => self.read_symbol()
+        })
+    }
+}
+
+fn parse_number(token: &str) -> Result<Object> {
+    if token == "." {
+        return Err(error("Unexpected '.'"));
     }
+    Ok(number::parse(token).unwrap_or_else(|| object::symbol(token)))
 }
 
-pub fn read<'a>(iter: impl 'a + Iterator<Item = char>) -> impl 'a + Iterator<Item = Object> {
-    ReaderIterator::new(iter)
+pub fn read<'a>(iter: impl 'a + Iterator<Item = char>) -> impl 'a + Iterator<Item = Result<Object>> {
+    ReaderIterator::new(iter).skip_shebang()
 }
 
-pub fn read_string(str: &str) -> Option<Object> {
+// Reads a single datum. A token ends at the char after it, which the
+// reader has already taken from `chars`, so that char is returned too for
+// the caller to read next.
+pub fn read_datum(chars: impl Iterator<Item = char>) -> (Option<Result<Object>>, Option<char>) {
+    let mut r = ReaderIterator::new(chars);
+    let datum = r.next();
+    (datum, r.peek)
+}
+
+pub fn read_string(str: &str) -> Option<Result<Object>> {
     read(str.chars()).next()
 }
 
 #[test]
 fn reader_internal_test() {
     let mut r = ReaderIterator::new("123abc".chars());
-    let s = r.read_while(|c| c.is_digit(10));
+    let s = r.read_while(|c| c.is_ascii_digit());
     assert_eq!(s, "123".to_string());
     assert_eq!(r.peek, Some('a'));
 
     let mut r = ReaderIterator::new("123abc".chars());
-    r.drop_while(|c| c.is_digit(10));
+    r.drop_while(|c| c.is_ascii_digit());
     assert_eq!(r.iter.collect::<String>(), "bc".to_string());
     assert_eq!(r.peek, Some('a'));
 }
 
 #[test]
 fn reader_test() {
-    let read = |s| read_string(s).unwrap();
+    let read = |s| read_string(s).unwrap().unwrap();
 
     assert_eq!(read("t"), Object::T);
     assert_eq!(read("nil"), Object::Nil);
     assert_eq!(read("-123"), Object::Number(-123));
-    assert_eq!(read("hello-world!"), Object::Symbol("hello-world!".to_string()));
+    assert_eq!(read("+123"), Object::Number(123));
+    assert_eq!(read("-"), object::symbol("-"));
+    assert_eq!(read("1+"), object::symbol("1+"));
+    assert_eq!(read("-2147483648"), Object::Number(i32::MIN));
+    assert_eq!(read("2147483648").to_string(), "2147483648".to_string());
+    assert_eq!(read("-1.5e3"), Object::Float(-1500.0));
+    assert_eq!(read("+inf.0"), Object::Float(f64::INFINITY));
+    assert_eq!(read("hello-world!"), object::symbol("hello-world!"));
+    assert_eq!(read(":key"), Object::Keyword(Symbol::intern("key")));
+    assert_eq!(read(":"), object::symbol(":"));
+    assert_eq!(read("\"a \\\"b\\\"\\n\""), Object::Str("a \"b\"\n".to_string()));
+    assert_eq!(read("#(1 [2] ) ").to_string(), "#(1 #(2))".to_string());
+    assert_eq!(read("#[1 #[2]]").to_string(), "#[1 #[2]]".to_string());
+    assert_eq!(read("{a 1}").to_string(), "{a 1}".to_string());
+    assert_eq!(read("#\\a"), Object::Char('a'));
+    assert_eq!(read("#\\("), Object::Char('('));
+    assert_eq!(read("#\\space"), Object::Char(' '));
+    assert_eq!(read("#\\x41"), Object::Char('A'));
+    assert_eq!(read("#\\x"), Object::Char('x'));
+    for src in ["#\\a", "#\\newline", "#\\x1", "#\\)", "#\\\u{3bb}"] {
+        assert_eq!(format!("{:#}", read(src)), src.to_string());
+    }
+    assert_eq!(read("'x"), object::vec_to_list(&[
+        Rc::new(object::symbol("quote")),
+        Rc::new(object::symbol("x"))
+    ]));
     assert_eq!(read("(1 2 3)"), Object::Cons(
         Rc::new(Object::Number(1)),
         Rc::new(Object::Cons(
@@ -160,3 +451,69 @@ fn reader_test() {
         ))
     ));
 }
+
+#[test]
+fn reader_error_test() {
+    let read = |s| read_string(s).unwrap().map_err(|e| e.to_string());
+
+    assert_eq!(read("(1 2"), Err("Missing closing ')'".to_string()));
+    assert_eq!(read("(1 2]"), Err("Expected ')' but found ']'".to_string()));
+    assert_eq!(read(")"), Err("Unexpected ')'".to_string()));
+    assert_eq!(read("\"abc"), Err("Unterminated string".to_string()));
+    assert_eq!(read("\"abc\\"), Err("Unterminated string".to_string()));
+    assert_eq!(read("'"), Err("Unexpected end of input after quote".to_string()));
+    assert_eq!(read("{a 1 b}"), Err("Map literal must have an even number of forms".to_string()));
+    assert!(read_string("(a").unwrap().unwrap_err().is_incomplete());
+    assert!(read_string("'").unwrap().unwrap_err().is_incomplete());
+    assert!(!read_string("(a]").unwrap().unwrap_err().is_incomplete());
+    assert_eq!(read("#\\foo"), Err("Unknown character name: #\\foo".to_string()));
+    assert_eq!(read("(. a)"), Err("Nothing before '.' in list".to_string()));
+    assert_eq!(read("(a .)"), Err("Nothing after '.' in list".to_string()));
+    assert_eq!(read("(a . b c)"), Err("More than one object after '.' in list".to_string()));
+    assert_eq!(read("(a . b"), Err("Missing closing ')'".to_string()));
+    assert_eq!(read("#(a . b)"), Err("Unexpected '.'".to_string()));
+    assert_eq!(read("(a #| b |#"), Err("Missing closing ')'".to_string()));
+    assert_eq!(read("#| #| |# x"), Err("Unterminated block comment".to_string()));
+    assert_eq!(read("(a #;"), Err("Unexpected end of input after #;".to_string()));
+}
+
+#[test]
+fn round_trip_test() {
+    let read = |s: &str| read_string(s).unwrap().unwrap();
+    let data = [
+        "(a . b)", "(a b . c)", "((a . b) (c . d) . e)", "(1 .5 -.5 .a)", "(quote . x)",
+        "(1 2147483648 -1/3 1.5 1e300 +inf.0 -inf.0 +nan.0)",
+        "(t nil :key #\\a #\\space #\\x7 #\\()", "#(1 (2 . 3) #[4 {5 6}])",
+        "(a.b ... 1+ -)", "(\"\" \"a \\\"b\\\" \\\\ \\n\\t\\r \\x7;\")",
+        "(|a b| |nil| |t| |12| |+inf.0| || |:k| |#x| |.| |a\\|b| |(|)",
+        "(:|a b| :|12| :|| :|:k| :|a\\|b| : |x|)"
+    ];
+    for src in data.iter() {
+        let obj = read(src);
+        let printed = format!("{:#}", obj);
+        assert_eq!(read(&printed), obj, "{} printed as {}", src, printed);
+    }
+    assert_eq!(read("(a b . (c d))").to_string(), "(a b c d)".to_string());
+    assert_eq!(read("(a . nil)").to_string(), "(a)".to_string());
+    assert_eq!(format!("{:#}", read("(\"a\\x3bb;\" |a b| #\\a)")), "(\"a\u{3bb}\" |a b| #\\a)".to_string());
+    assert_eq!(read("(\"a\" |a b| #\\a)").to_string(), "(a a b a)".to_string());
+    assert_eq!(format!("{:#}", read("(:|a b| :|k|)")), "(:|a b| :k)".to_string());
+    assert_eq!(read("(:|a b| :|k|)").to_string(), "(:a b :k)".to_string());
+    assert_eq!(read_string("|a").unwrap().map_err(|e| e.to_string()), Err("Unterminated |symbol|".to_string()));
+}
+
+#[test]
+fn comment_test() {
+    let read_all = |s: &str| read(s.chars()).map(|obj| obj.unwrap().to_string()).collect::<Vec<_>>();
+
+    assert_eq!(read_all("; comment\n1 ; another\n2;x"), vec!["1", "2"]);
+    assert_eq!(read_all("(a;c\n b)"), vec!["(a b)"]);
+    assert_eq!(read_all("#| a #| nested |# still |# 1 #||# 2 #|x||#"), vec!["1", "2"]);
+    assert_eq!(read_all("(1 #|x|#)"), vec!["(1)"]);
+    assert_eq!(read_all("(1 #;(2 3) 4 #; #; 5 6) #;7"), vec!["(1 4)"]);
+    assert_eq!(read_all("(a . #;b c)"), vec!["(a . c)"]);
+    assert_eq!(read_all("#(1 #;2) #\\; #\\#"), vec!["#(1)", ";", "#"]);
+    assert_eq!(read_all("1 "), vec!["1"]);
+    assert_eq!(read_all("#!/usr/bin/env rusp\n(a)"), vec!["(a)"]);
+    assert_eq!(read_all("#!"), Vec::<String>::new());
+}
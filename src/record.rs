@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::result;
+use crate::error::{Error, error};
+use crate::object::{self, Native, Object};
+use crate::symbol::Symbol;
+
+pub type Result<T> = result::Result<T, Error>;
+
+// A type created by `define-record-type`. `constructor` holds the index of
+// the field set by each constructor argument; the other fields start as
+// nil.
+#[derive(Debug)]
+pub struct RecordType {
+    name: Symbol,
+    fields: Vec<Symbol>,
+    constructor: Vec<usize>
+}
+
+impl RecordType {
+    pub fn new(name: Symbol, fields: Vec<Symbol>, constructor: Vec<usize>) -> Self {
+        RecordType { name, fields, constructor }
+    }
+
+    // The type name without the conventional angle brackets.
+    pub fn name(&self) -> String {
+        let name = self.name.name();
+        name.strip_prefix('<').and_then(|s| s.strip_suffix('>')).unwrap_or(&name).to_string()
+    }
+
+    pub fn fields(&self) -> &[Symbol] {
+        &self.fields
+    }
+
+    pub fn field_index(&self, field: Symbol) -> Option<usize> {
+        self.fields.iter().position(|f| *f == field)
+    }
+}
+
+fn record_type(obj: &Object) -> &Rc<RecordType> {
+    match obj {
+        Object::RecordType(rtd) => rtd,
+        _ => unreachable!("record procedures are compiled with their type")
+    }
+}
+
+fn fields<'a>(rtd: &RecordType, obj: &'a Object) -> Result<&'a RefCell<Vec<Rc<Object>>>> {
+    match obj {
+        Object::Record(t, fields) if std::ptr::eq(t.as_ref(), rtd) => Ok(fields),
+        _ => {
+            let msg = format!("{} is not a record of type {}", obj, rtd.name());
+            Err(error(&msg))
+        }
+    }
+}
+
+fn index(obj: &Object) -> usize {
+    match *obj {
+        Object::Number(i) => i as usize,
+        _ => unreachable!("record procedures are compiled with field indices")
+    }
+}
+
+// The procedures defined by `define-record-type` are closures that pass
+// the record type (and field index) to one of these.
+pub const CONSTRUCTOR: Native = Native { name: "record-constructor", arity: (1, None), func: |_, args| {
+    let rtd = record_type(&args[0]);
+    let nil = Rc::new(Object::Nil);
+    let mut fields = vec![nil; rtd.fields.len()];
+    for (i, arg) in rtd.constructor.iter().zip(&args[1..]) {
+        fields[*i] = arg.clone();
+    }
+    Ok(Rc::new(Object::Record(rtd.clone(), RefCell::new(fields))))
+}};
+
+pub const PREDICATE: Native = Native { name: "record-predicate", arity: (2, Some(2)), func: |_, args| {
+    let rtd = record_type(&args[0]);
+    Ok(Rc::new(object::from_bool(fields(rtd, &args[1]).is_ok())))
+}};
+
+pub const ACCESSOR: Native = Native { name: "record-accessor", arity: (3, Some(3)), func: |_, args| {
+    let fields = fields(record_type(&args[0]), &args[2])?;
+    let v = fields.borrow()[index(&args[1])].clone();
+    Ok(v)
+}};
+
+pub const MODIFIER: Native = Native { name: "record-modifier", arity: (4, Some(4)), func: |_, args| {
+    let fields = fields(record_type(&args[0]), &args[2])?;
+    fields.borrow_mut()[index(&args[1])] = args[3].clone();
+    Ok(Rc::new(Object::Nil))
+}};
@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// A symbol is an index into the symbol table, so comparing and hashing
+// symbols never touches their names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>
+}
+
+// The special forms and inlined primitives, which the compiler looks for
+// by id. They are interned before any other symbol.
+pub const QUOTE: Symbol = Symbol(0);
+pub const IF: Symbol = Symbol(1);
+pub const LAMBDA: Symbol = Symbol(2);
+pub const BEGIN: Symbol = Symbol(3);
+pub const DEFINE: Symbol = Symbol(4);
+pub const DEFINE_RECORD_TYPE: Symbol = Symbol(5);
+pub const MODULE: Symbol = Symbol(6);
+pub const IMPORT: Symbol = Symbol(7);
+pub const EXPORT: Symbol = Symbol(8);
+pub const ADD: Symbol = Symbol(9);
+pub const SUB: Symbol = Symbol(10);
+pub const MUL: Symbol = Symbol(11);
+pub const DIV: Symbol = Symbol(12);
+pub const EQ: Symbol = Symbol(13);
+pub const LT: Symbol = Symbol(14);
+pub const GT: Symbol = Symbol(15);
+pub const LE: Symbol = Symbol(16);
+pub const GE: Symbol = Symbol(17);
+pub const CONS: Symbol = Symbol(18);
+pub const CAR: Symbol = Symbol(19);
+pub const CDR: Symbol = Symbol(20);
+pub const NULL: Symbol = Symbol(21);
+pub const ATOM: Symbol = Symbol(22);
+
+const PREDEFINED: [(Symbol, &str); 23] = [
+    (QUOTE, "quote"),
+    (IF, "if"),
+    (LAMBDA, "lambda"),
+    (BEGIN, "begin"),
+    (DEFINE, "define"),
+    (DEFINE_RECORD_TYPE, "define-record-type"),
+    (MODULE, "module"),
+    (IMPORT, "import"),
+    (EXPORT, "export"),
+    (ADD, "+"),
+    (SUB, "-"),
+    (MUL, "*"),
+    (DIV, "/"),
+    (EQ, "="),
+    (LT, "<"),
+    (GT, ">"),
+    (LE, "<="),
+    (GE, ">="),
+    (CONS, "cons"),
+    (CAR, "car"),
+    (CDR, "cdr"),
+    (NULL, "null"),
+    (ATOM, "atom"),
+];
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+impl SymbolTable {
+    fn new() -> Self {
+        let mut table = SymbolTable { names: Vec::new(), ids: HashMap::new() };
+        for (sym, name) in PREDEFINED {
+            let name: Rc<str> = Rc::from(name);
+            table.ids.insert(name.clone(), sym);
+            table.names.push(name);
+        }
+        table
+    }
+
+    fn add(&mut self, name: Rc<str>) -> Symbol {
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        sym
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Self {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(sym) = table.ids.get(name) {
+                return *sym;
+            }
+            let name: Rc<str> = Rc::from(name);
+            let sym = table.add(name.clone());
+            table.ids.insert(name, sym);
+            sym
+        })
+    }
+
+    // Makes a fresh symbol that is never returned by `intern`, even for
+    // the same name.
+    pub fn gensym(prefix: &str) -> Self {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            let name = format!("{}{}", prefix, table.names.len());
+            table.add(Rc::from(name))
+        })
+    }
+
+    pub fn name(self) -> Rc<str> {
+        SYMBOLS.with(|table| table.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[test]
+fn symbol_test() {
+    let foo = Symbol::intern("foo");
+    assert_eq!(foo, Symbol::intern("foo"));
+    assert_ne!(foo, Symbol::intern("bar"));
+    assert_eq!(&*foo.name(), "foo");
+
+    for (sym, name) in PREDEFINED {
+        assert_eq!(Symbol::intern(name), sym);
+        assert_eq!(&*sym.name(), name);
+    }
+
+    let g = Symbol::gensym("g");
+    assert_ne!(g, Symbol::intern(&g.name()));
+    assert_ne!(g, Symbol::gensym("g"));
+}
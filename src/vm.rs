@@ -1,11 +1,18 @@
+use std::cmp::Ordering;
 use std::mem;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::result;
+use crate::compiler;
 use crate::env;
 use crate::error::{Error, error};
 use crate::insns::{Code, Insn};
 use crate::insns::Insn::*;
+use crate::module::{self, Modules};
+use crate::namespace::Namespace;
+use crate::number;
 use crate::object::{self, Object};
+use crate::port::Port;
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -23,16 +30,115 @@ pub struct Vm {
     env: Env,
     code: Code,
     dump: Dump,
-    pc: Pc
+    pc: Pc,
+    ns: Rc<Namespace>,
+    modules: Modules,
+    command_line: Vec<String>,
+    input: Rc<Port>,
+    output: Rc<Port>
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new(Rc::new(vec![]))
+    }
 }
 
 impl Vm {
     pub fn new(code: Code) -> Self {
         Vm { stack: vec![],
              env: Rc::new(env::Env::new()),
-             code: code,
+             code,
              dump: vec![],
-             pc: 0 }
+             pc: 0,
+             ns: Rc::new(Namespace::with_parent(Rc::new(Namespace::new()))),
+             modules: Modules::default(),
+             command_line: vec![],
+             input: Rc::new(Port::stdin()),
+             output: Rc::new(Port::stdout()) }
+    }
+
+    // The namespace top-level definitions go into. Its parent holds only
+    // the natives and the prelude, which is what modules see.
+    pub fn namespace(&self) -> &Rc<Namespace> {
+        &self.ns
+    }
+
+    // Replaces the namespace that code is compiled against, returning the
+    // previous one.
+    pub(crate) fn set_namespace(&mut self, ns: Rc<Namespace>) -> Rc<Namespace> {
+        mem::replace(&mut self.ns, ns)
+    }
+
+    pub(crate) fn modules(&mut self) -> &mut Modules {
+        &mut self.modules
+    }
+
+    // Adds a directory where `import` looks for module files, after the
+    // directory of the file being loaded.
+    pub fn add_module_path(&mut self, dir: impl Into<PathBuf>) {
+        self.modules.add_search_path(dir.into());
+    }
+
+    // The list returned by `command-line`: the program or script name,
+    // followed by its arguments.
+    pub fn command_line(&self) -> &[String] {
+        &self.command_line
+    }
+
+    pub fn set_command_line(&mut self, args: Vec<String>) {
+        self.command_line = args;
+    }
+
+    // The ports used by `read`, `display` and the like when no port is
+    // given. They start as standard input and output.
+    pub fn input_port(&self) -> &Rc<Port> {
+        &self.input
+    }
+
+    pub fn output_port(&self) -> &Rc<Port> {
+        &self.output
+    }
+
+    // Replaces the current input port, returning the previous one.
+    pub fn set_input_port(&mut self, port: Rc<Port>) -> Rc<Port> {
+        mem::replace(&mut self.input, port)
+    }
+
+    // Replaces the current output port, returning the previous one. Output
+    // can be captured by setting a port made with `Port::output_string`.
+    pub fn set_output_port(&mut self, port: Rc<Port>) -> Rc<Port> {
+        mem::replace(&mut self.output, port)
+    }
+
+    // Compiles `expr` against the VM's namespace and runs it. Whatever was
+    // left over by an earlier failed run is discarded first.
+    pub fn eval(&mut self, expr: &Object) -> Result<Rc<Object>> {
+        let code = compiler::compile(expr, &self.ns)?;
+        self.reset(code);
+        self.run()
+    }
+
+    // Evaluates every expression in the file, like `load`.
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<Rc<Object>> {
+        self.reset(Rc::new(vec![]));
+        module::load(self, path.as_ref())
+    }
+
+    fn reset(&mut self, code: Code) {
+        self.stack.clear();
+        self.dump.clear();
+        self.env = Rc::new(env::Env::new());
+        self.code = code;
+        self.pc = 0;
+    }
+
+    // Like `eval`, but may be called from native code while running.
+    pub(crate) fn eval_nested(&mut self, expr: &Object) -> Result<Rc<Object>> {
+        let mut code = compiler::compile(expr, &self.ns)?.as_ref().clone();
+        code.push(Irtn);
+        let func = Object::Func(Rc::new(code), Rc::new(env::Env::new()));
+        self.apply(&func, vec![])
     }
 
     fn fetch_insn(&self) -> Option<Insn> {
@@ -55,79 +161,136 @@ impl Vm {
         self.dump.pop().ok_or(error("Dump stack underflow"))
     }
 
-    fn binary_op(&mut self, op: impl FnOnce(i32, i32) -> Rc<Object>) -> Result<()> {
-        let y = self.pop()?.to_number()?;
-        let x = self.pop()?.to_number()?;
-        self.push(op(x, y));
+    fn binary_op(&mut self, op: impl FnOnce(&Object, &Object) -> Result<Object>) -> Result<()> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        self.push(Rc::new(op(&x, &y)?));
         Ok(())
     }
 
-    fn arith_op(&mut self, op: impl FnOnce(i32, i32) -> i32) -> Result<()> {
-        self.binary_op(|x, y| Rc::new(Object::Number(op(x, y))))
-    }
-
-    fn logical_op(&mut self, op: impl FnOnce(i32, i32) -> bool) -> Result<()> {
-        self.binary_op(|x, y| Rc::new(object::from_bool(op(x, y))))
+    fn logical_op(&mut self, op: impl FnOnce(Option<Ordering>) -> bool) -> Result<()> {
+        self.binary_op(|x, y| Ok(object::from_bool(op(number::compare(x, y)?))))
     }
 
     pub fn run(&mut self) -> Result<Rc<Object>> {
         while let Some(insn) = self.fetch_insn() {
-            match insn {
-                Inil => self.push(Rc::new(Object::Nil)),
-                Ildc(obj) => self.push(obj.clone()),
-                Ild(loc) => {
-                    let obj = self.env.locate(loc)?;
-                    self.push(obj);
-                }
-                Iatom => {
-                    let obj = self.pop()?;
-                    self.push(Rc::new(object::from_bool(obj.is_atom())));
-                }
-                Inull => {
-                    let obj = self.pop()?;
-                    self.push(Rc::new(object::from_bool(obj.is_null())));
-                }
-                Icons => {
-                    let x = self.pop()?;
-                    let y = self.pop()?;
-                    self.push(Rc::new(object::cons(x, y)));
-                }
-                Icar => {
-                    let obj = self.pop()?;
-                    self.push(obj.car()?);
-                }
-                Icdr => {
-                    let obj = self.pop()?;
-                    self.push(obj.cdr()?);
-                }
-                Iadd => self.arith_op(std::ops::Add::add)?,
-                Isub => self.arith_op(std::ops::Sub::sub)?,
-                Imul => self.arith_op(std::ops::Mul::mul)?,
-                Idiv => self.arith_op(std::ops::Div::div)?,
-                Ieq  => self.logical_op(|x, y| x == y)?,
-                Igt  => self.logical_op(|x, y| x > y)?,
-                Ilt  => self.logical_op(|x, y| x < y)?,
-                Igte => self.logical_op(|x, y| x >= y)?,
-                Ilte => self.logical_op(|x, y| x <= y)?,
-                Isel(ct, cf) => {
-                    self.run_sel(ct, cf)?;
-                    continue;
-                }
-                Ijoin => self.run_join()?,
-                Ildf(code) => {
-                    let env = self.env.clone();
-                    let obj = Object::Func(code.clone(), env);
-                    self.push(Rc::new(obj));
-                }
-                Iap => {
-                    self.run_ap()?;
-                    continue;
+            self.step(insn)?;
+        }
+        Ok(self.pop().unwrap_or(Rc::new(Object::Nil)))
+    }
+
+    // Calls `func` with `args` and returns its value. A closure runs on a
+    // new dump entry until it returns to the caller's instruction, so this
+    // may be called both by the host and by natives while the VM is
+    // running. On error, the frames of the call are dropped and the caller
+    // is left as it was.
+    pub fn apply(&mut self, func: &Object, args: Vec<Rc<Object>>) -> Result<Rc<Object>> {
+        match func {
+            Object::Func(code, env) => {
+                let (depth, pc) = (self.dump.len(), self.pc);
+                self.enter(code.clone(), env.clone(), args)?;
+                let ret = self.run_until(depth, pc);
+                if ret.is_err() {
+                    self.unwind(depth);
                 }
-                Irtn => self.run_rtn()?
+                ret
+            }
+            Object::Native(native) => {
+                native.check_arity(args.len())?;
+                (native.func)(self, &args)
             }
-            self.pc += 1;
+            _ => Err(error("Can't apply object other than function"))
+        }
+    }
+
+    // Runs until the closure entered at `depth` returns to `pc`, and takes
+    // its value off the caller's stack.
+    fn run_until(&mut self, depth: usize, pc: Pc) -> Result<Rc<Object>> {
+        while self.dump.len() > depth {
+            let insn = self.fetch_insn().ok_or(error("Function ended without return"))?;
+            self.step(insn)?;
+        }
+        self.pc = pc;
+        self.pop()
+    }
+
+    // Restores the state saved when the closure at `depth` was entered.
+    fn unwind(&mut self, depth: usize) {
+        self.dump.truncate(depth + 1);
+        if let Some(DumpEntry::Ap(stack, env, code, pc)) = self.dump.pop() {
+            self.stack = stack;
+            self.env = env;
+            self.code = code;
+            self.pc = pc;
         }
-        Ok(self.pop().unwrap_or(Rc::new(Object::Nil)))
+    }
+
+    fn step(&mut self, insn: Insn) -> Result<()> {
+        match insn {
+            Inil => self.push(Rc::new(Object::Nil)),
+            Ildc(obj) => self.push(obj.clone()),
+            Ild(loc) => {
+                let obj = self.env.locate(loc)?;
+                self.push(obj);
+            }
+            Igld(global) => {
+                let obj = global.get().ok_or_else(|| {
+                    error(&format!("unbound variable: {}", global.name()))
+                })?;
+                self.push(obj);
+            }
+            Idef(global) => {
+                let obj = self.pop()?;
+                global.set(obj);
+                self.push(Rc::new(Object::Symbol(global.name())));
+            }
+            Ipop => {
+                self.pop()?;
+            }
+            Iatom => {
+                let obj = self.pop()?;
+                self.push(Rc::new(object::from_bool(obj.is_atom())));
+            }
+            Inull => {
+                let obj = self.pop()?;
+                self.push(Rc::new(object::from_bool(obj.is_null())));
+            }
+            Icons => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                self.push(Rc::new(object::cons(x, y)));
+            }
+            Icar => {
+                let obj = self.pop()?;
+                self.push(obj.car()?);
+            }
+            Icdr => {
+                let obj = self.pop()?;
+                self.push(obj.cdr()?);
+            }
+            Iadd => self.binary_op(number::add)?,
+            Isub => self.binary_op(number::sub)?,
+            Imul => self.binary_op(number::mul)?,
+            Idiv => self.binary_op(number::div)?,
+            Ieq  => self.logical_op(|o| o == Some(Ordering::Equal))?,
+            Igt  => self.logical_op(|o| o == Some(Ordering::Greater))?,
+            Ilt  => self.logical_op(|o| o == Some(Ordering::Less))?,
+            Igte => self.logical_op(|o| o.is_some_and(Ordering::is_ge))?,
+            Ilte => self.logical_op(|o| o.is_some_and(Ordering::is_le))?,
+            Isel(ct, cf) => return self.run_sel(ct, cf),
+            Ijoin => self.run_join()?,
+            Ildf(code) => {
+                let env = self.env.clone();
+                let obj = Object::Func(code.clone(), env);
+                self.push(Rc::new(obj));
+            }
+            // Checked when the function is entered.
+            Iargs(..) => (),
+            Iap => return self.run_ap(),
+            Irtn => self.run_rtn()?
+        }
+        self.pc += 1;
+        Ok(())
     }
 
     fn run_sel(&mut self, ct: Code, cf: Code) -> Result<()> {
@@ -160,19 +323,35 @@ impl Vm {
             Object::Func(ref code, ref env) => {
                 let args = self.pop()?;
                 let frame = object::list_to_vec(args.as_ref())?;
-                let stack = mem::replace(&mut self.stack, vec![]);
-                let new_env = env::push(env.clone(), frame);
-                let env = mem::replace(&mut self.env, Rc::new(new_env));
-                let code = mem::replace(&mut self.code, code.clone());
-                let entry = DumpEntry::Ap(stack, env, code, self.pc);
-                self.dump.push(entry);
-                self.pc = 0;
+                self.enter(code.clone(), env.clone(), frame)
+            }
+            Object::Native(ref native) => {
+                let args = self.pop()?;
+                let args = object::list_to_vec(args.as_ref())?;
+                native.check_arity(args.len())?;
+                let v = (native.func)(self, &args)?;
+                self.push(v);
+                self.pc += 1;
                 Ok(())
             }
             _ => Err(error("Can't apply object other than function"))
         }
     }
 
+    fn enter(&mut self, code: Code, env: Env, mut frame: Vec<Rc<Object>>) -> Result<()> {
+        if let Some(Iargs(required, rest)) = code.first() {
+            frame = bind_args(*required, *rest, frame)?;
+        }
+        let stack = mem::take(&mut self.stack);
+        let new_env = env::push(env, frame);
+        let env = mem::replace(&mut self.env, Rc::new(new_env));
+        let code = mem::replace(&mut self.code, code);
+        let entry = DumpEntry::Ap(stack, env, code, self.pc);
+        self.dump.push(entry);
+        self.pc = 0;
+        Ok(())
+    }
+
     fn run_rtn(&mut self) -> Result<()> {
         let v = self.pop()?;
         match self.dump_pop()? {
@@ -189,6 +368,21 @@ impl Vm {
     }
 }
 
+// Checks the number of arguments, and collects the ones after the
+// required ones into a list if the function takes the rest.
+fn bind_args(required: usize, rest: bool, mut frame: Vec<Rc<Object>>) -> Result<Vec<Rc<Object>>> {
+    if frame.len() < required {
+        return Err(error("too less arguments"));
+    }
+    if rest {
+        let tail = frame.split_off(required);
+        frame.push(Rc::new(object::vec_to_list(&tail)));
+    } else if frame.len() > required {
+        return Err(error("too many arguments"));
+    }
+    Ok(frame)
+}
+
 #[test]
 fn vm_test() {
     let code = Rc::new(vec![
@@ -232,3 +426,67 @@ fn vm_test() {
     let v = vm.run().expect("VM never fails");
     assert_eq!(v, Rc::new(Object::Number(12)));
 }
+
+#[test]
+fn arith_test() {
+    let run = |x: i32, y: i32, insn: Insn| {
+        let code = Rc::new(vec![
+            Ildc(Rc::new(Object::Number(x))),
+            Ildc(Rc::new(Object::Number(y))),
+            insn
+        ]);
+        Vm::new(code).run().map(|v| v.to_string()).map_err(|e| e.to_string())
+    };
+    assert_eq!(run(1, 0, Idiv), Err("Division by zero".to_string()));
+    assert_eq!(run(100000, 100000, Imul), Ok("10000000000".to_string()));
+    assert_eq!(run(i32::MAX, 1, Iadd), Ok("2147483648".to_string()));
+    assert_eq!(run(i32::MIN, 1, Isub), Ok("-2147483649".to_string()));
+    assert_eq!(run(i32::MIN, -1, Idiv), Ok("2147483648".to_string()));
+    assert_eq!(run(7, 2, Idiv), Ok("7/2".to_string()));
+}
+
+#[test]
+fn eval_test() {
+    let mut vm = Vm::default();
+    vm.set_command_line(vec!["script".to_string(), "arg".to_string()]);
+    let mut eval = |src| {
+        let expr = crate::reader::read_string(src).unwrap().unwrap();
+        vm.eval(&expr).map(|v| v.to_string()).map_err(|e| e.to_string())
+    };
+    assert_eq!(eval("(define x 1)"), Ok("x".to_string()));
+    assert_eq!(eval("(car 1)"), Err("Can't be converted to cons".to_string()));
+    assert_eq!(eval("(+ x 1)"), Ok("2".to_string()));
+    assert_eq!(eval("(command-line)"), Ok("(script arg)".to_string()));
+}
+
+#[test]
+fn apply_test() {
+    let mut vm = Vm::default();
+    let eval = |vm: &mut Vm, src: &str| {
+        vm.eval(&crate::reader::read_string(src).unwrap().unwrap()).unwrap()
+    };
+    let add = eval(&mut vm, "(lambda (x y) (+ x y))");
+    let div = eval(&mut vm, "(lambda (x y) (/ x (car y)))");
+    let num = |i| Rc::new(Object::Number(i));
+    assert_eq!(vm.apply(&add, vec![num(1), num(2)]).map(|v| v.to_string()).map_err(|e| e.to_string()), Ok("3".to_string()));
+    assert!(vm.apply(&add, vec![num(1)]).is_err());
+    assert!(vm.apply(&div, vec![num(1), num(2)]).is_err());
+    assert_eq!(vm.apply(&add, vec![num(3), num(4)]).map(|v| v.to_string()).map_err(|e| e.to_string()), Ok("7".to_string()));
+
+    // An error inside a closure called by a native is returned to the host.
+    let sort = eval(&mut vm, "(lambda (xs) (sort xs (lambda (x y) (< x (car y)))))");
+    let list = Rc::new(object::vec_to_list(&[num(2), num(1)]));
+    assert!(vm.apply(&sort, vec![list]).is_err());
+    assert_eq!(vm.apply(&add, vec![num(5), num(6)]).map(|v| v.to_string()).map_err(|e| e.to_string()), Ok("11".to_string()));
+}
+
+#[test]
+fn output_port_test() {
+    let mut vm = Vm::default();
+    let out = Rc::new(Port::output_string());
+    let stdout = vm.set_output_port(out.clone());
+    let expr = crate::reader::read_string("(begin (display \"x = \") (write \"1\") (newline))").unwrap().unwrap();
+    vm.eval(&expr).unwrap();
+    vm.set_output_port(stdout);
+    assert_eq!(out.contents(), Some("x = \"1\"\n".to_string()));
+}